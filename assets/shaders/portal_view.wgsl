@@ -4,16 +4,46 @@
     utils::coords_to_viewport_uv,
 }
 
+struct PortalViewSettings {
+    color: vec4<f32>,
+    openness: f32,
+    // 1 for an elliptical portal, 0 for a rectangular one
+    ellipse: u32,
+    // 1 when the view is shown, 0 for just the color of an unpaired portal
+    view: u32,
+}
+
 @group(2) @binding(0) var texture: texture_2d<f32>;
 @group(2) @binding(1) var texture_sampler: sampler;
+@group(2) @binding(2) var<uniform> settings: PortalViewSettings;
+
+// The number of cells across the portal in which the view dissolves in
+const DISSOLVE_CELLS: f32 = 24.0;
+
+fn hash(p: vec2<f32>) -> f32 {
+    return fract(sin(dot(p, vec2<f32>(12.9898, 78.233))) * 43758.5453);
+}
 
 @fragment
 fn fragment(
     mesh: VertexOutput,
 ) -> @location(0) vec4<f32> {
+    let uv = mesh.uv * 2.0 - 1.0;
+
+    // grows out of the center while opening, and shrinks back into it while closing
+    if settings.ellipse != 0u {
+        if dot(uv, uv) > settings.openness * settings.openness {
+            discard;
+        }
+    } else if any(abs(uv) > vec2<f32>(settings.openness)) {
+        discard;
+    }
+
+    // the cells of the view dissolve in over the portal's color in a random order
+    let dissolved = step(hash(floor(mesh.uv * DISSOLVE_CELLS)), settings.openness) * f32(settings.view);
+
     let viewport_uv = coords_to_viewport_uv(mesh.position.xy, view.viewport);
     let color = textureSample(texture, texture_sampler, viewport_uv);
-    let uv = mesh.uv * 2.0 - 1.0;
     let alpha = 1.0 - dot(uv, uv);
-    return vec4<f32>(color.rgb, alpha);
-}
\ No newline at end of file
+    return vec4<f32>(mix(settings.color.rgb, color.rgb, dissolved), alpha);
+}
@@ -1,8 +1,8 @@
-use bevy::{color::palettes, prelude::*};
+use bevy::prelude::*;
 
 use super::{
     player::{Grounded, Player},
-    portal::{Portal1, Portal2, PortalSurface},
+    portal::{PortalChannel, PortalOutline, PortalPairs, PortalShape, PortalSurface},
 };
 
 #[derive(Component)]
@@ -79,28 +79,21 @@ pub fn portal_surface_gizmo(
 }
 
 pub fn portal_gizmo(
-    portal1_q: Query<&GlobalTransform, With<Portal1>>,
-    portal2_q: Query<&GlobalTransform, With<Portal2>>,
+    portal_q: Query<(&GlobalTransform, &PortalChannel, &PortalShape)>,
+    portal_pairs: Res<PortalPairs>,
     mut gizmos: Gizmos,
 ) {
-    for transform in portal1_q.iter() {
+    for (transform, &channel, shape) in portal_q.iter() {
+        let color = portal_pairs.color(channel);
         let (scale, rotation, translation) = transform.to_scale_rotation_translation();
-        gizmos.rect(
-            translation,
-            rotation,
-            Vec2::new(1., 2.) * scale.xy(),
-            palettes::css::BLUE,
-        );
-        gizmos.ray(translation, *transform.back(), palettes::css::BLUE);
-    }
-    for transform in portal2_q.iter() {
-        let (scale, rotation, translation) = transform.to_scale_rotation_translation();
-        gizmos.rect(
-            translation,
-            rotation,
-            Vec2::new(1., 2.) * scale.xy(),
-            palettes::css::ORANGE,
-        );
-        gizmos.ray(translation, *transform.back(), palettes::css::ORANGE);
+        match shape.outline {
+            PortalOutline::Rectangle => {
+                gizmos.rect(translation, rotation, shape.size * scale.xy(), color);
+            }
+            PortalOutline::Ellipse => {
+                gizmos.ellipse(translation, rotation, shape.half_size() * scale.xy(), color);
+            }
+        }
+        gizmos.ray(translation, *transform.back(), color);
     }
 }
@@ -1,10 +1,19 @@
 use bevy::{
     app::AppExit,
+    input::mouse::MouseMotion,
     prelude::*,
     window::{CursorGrabMode, PrimaryWindow, WindowCloseRequested},
 };
 
-use crate::resource::{Controls, ControlsConfig};
+use crate::{
+    physics::{PhysicsTime, PhysicsTimeExt},
+    resource::{Control, Controls, ControlsConfig, LookControls, Pause, PhysicsControlsConfig},
+};
+
+/// The rotation speed of the look keys at the mouse sensitivity of `1`, rad/s
+const KEYS_LOOK_SPEED: f32 = 2.;
+/// The speed of the simulation in the slow motion
+const SLOW_MOTION_SPEED: f32 = 0.25;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemSet)]
 pub struct ButtonInputReactions;
@@ -21,39 +30,125 @@ pub fn setup(
     primary_window.cursor.visible = false;
 }
 
+/// Pauses, steps and slows down the simulation.
+///
+/// The virtual time is paused along with it, so that the portals and the projectiles freeze too.
+/// The cursor is released while paused, so that the simulation can be inspected.
+pub fn physics_time_controls(
+    input_key_code: Res<ButtonInput<KeyCode>>,
+    input_mouse_button: Res<ButtonInput<MouseButton>>,
+    controls_config: Res<PhysicsControlsConfig>,
+    mut pause: ResMut<Pause>,
+    mut physics_time: ResMut<PhysicsTime>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+    mut window_query: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    let just_pressed =
+        |control: Control| control.just_pressed(&input_key_code, &input_mouse_button);
+
+    if just_pressed(controls_config.pause) {
+        pause.0 = !pause.0;
+        if pause.0 {
+            physics_time.pause();
+            virtual_time.pause();
+        } else {
+            physics_time.resume();
+            virtual_time.unpause();
+        }
+
+        if let Ok(mut primary_window) = window_query.get_single_mut() {
+            primary_window.cursor.grab_mode = if pause.0 {
+                CursorGrabMode::None
+            } else {
+                CursorGrabMode::Locked
+            };
+            primary_window.cursor.visible = pause.0;
+        }
+    }
+
+    if pause.0 && just_pressed(controls_config.step) {
+        physics_time.step();
+    }
+
+    if just_pressed(controls_config.slow_motion) {
+        let speed = if physics_time.speed() == 1. {
+            SLOW_MOTION_SPEED
+        } else {
+            1.
+        };
+        physics_time.set_speed(speed);
+    }
+}
+
 pub fn input_mappings(
     input_key_code: Res<ButtonInput<KeyCode>>,
     input_mouse_button: Res<ButtonInput<MouseButton>>,
-    controls_config: Res<ControlsConfig>,
-    mut controls: ResMut<Controls>,
+    pause: Res<Pause>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    mut player_q: Query<(&ControlsConfig, &mut Controls)>,
 ) {
-    controls.up = controls_config
-        .up
-        .pressed(&input_key_code, &input_mouse_button);
-    controls.down = controls_config
-        .down
-        .pressed(&input_key_code, &input_mouse_button);
-    controls.left = controls_config
-        .left
-        .pressed(&input_key_code, &input_mouse_button);
-    controls.right = controls_config
-        .right
-        .pressed(&input_key_code, &input_mouse_button);
-    controls.run = controls_config
-        .run
-        .pressed(&input_key_code, &input_mouse_button);
-    controls.jump = controls_config
-        .jump
-        .just_pressed(&input_key_code, &input_mouse_button);
-    controls.shoot1 = controls_config
-        .shoot1
-        .just_pressed(&input_key_code, &input_mouse_button);
-    controls.shoot2 = controls_config
-        .shoot2
-        .just_pressed(&input_key_code, &input_mouse_button);
-    controls.remove_portals = controls_config
-        .remove_portals
-        .just_pressed(&input_key_code, &input_mouse_button);
+    let mouse_look = mouse_motion
+        .read()
+        .fold(Vec2::ZERO, |look, motion| look + motion.delta);
+
+    for (controls_config, mut controls) in player_q.iter_mut() {
+        // the players are frozen while paused
+        if pause.0 {
+            *controls = Controls::default();
+            continue;
+        }
+
+        controls.up = controls_config
+            .up
+            .pressed(&input_key_code, &input_mouse_button);
+        controls.down = controls_config
+            .down
+            .pressed(&input_key_code, &input_mouse_button);
+        controls.left = controls_config
+            .left
+            .pressed(&input_key_code, &input_mouse_button);
+        controls.right = controls_config
+            .right
+            .pressed(&input_key_code, &input_mouse_button);
+        controls.run = controls_config
+            .run
+            .pressed(&input_key_code, &input_mouse_button);
+        // held rather than just pressed, so that the physics ticks in between frames see it
+        controls.jump = controls_config
+            .jump
+            .pressed(&input_key_code, &input_mouse_button);
+        controls.shoot1 = controls_config
+            .shoot1
+            .just_pressed(&input_key_code, &input_mouse_button);
+        controls.shoot2 = controls_config
+            .shoot2
+            .just_pressed(&input_key_code, &input_mouse_button);
+        controls.remove_portals = controls_config
+            .remove_portals
+            .just_pressed(&input_key_code, &input_mouse_button);
+        controls.close_portal1 = controls_config
+            .close_portal1
+            .just_pressed(&input_key_code, &input_mouse_button);
+        controls.close_portal2 = controls_config
+            .close_portal2
+            .just_pressed(&input_key_code, &input_mouse_button);
+        controls.look = match controls_config.look {
+            LookControls::Mouse => mouse_look,
+            LookControls::Keys {
+                up,
+                down,
+                left,
+                right,
+            } => {
+                let pressed =
+                    |control: Control| control.pressed(&input_key_code, &input_mouse_button);
+                Vec2::new(
+                    if pressed(right) { 1. } else { 0. } - if pressed(left) { 1. } else { 0. },
+                    if pressed(down) { 1. } else { 0. } - if pressed(up) { 1. } else { 0. },
+                ) * KEYS_LOOK_SPEED
+            }
+        };
+    }
 }
 
 pub fn exit_on_primary_close(
@@ -72,9 +167,10 @@ pub fn exit_on_primary_close(
 pub fn cursor_grab(
     mut window_query: Query<&mut Window, With<PrimaryWindow>>,
     input: Res<ButtonInput<MouseButton>>,
+    pause: Res<Pause>,
     mut exit: EventWriter<AppExit>,
 ) {
-    if input.any_just_pressed([MouseButton::Left, MouseButton::Right]) {
+    if !pause.0 && input.any_just_pressed([MouseButton::Left, MouseButton::Right]) {
         let Ok(mut primary_window) = window_query.get_single_mut() else {
             exit.send(AppExit::Success);
             return;
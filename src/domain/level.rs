@@ -0,0 +1,135 @@
+use bevy::{gltf::GltfExtras, prelude::*};
+
+use super::scene::portal_surface_bundle;
+
+/// Marks a glTF node, whose meshes get a [`PortalSurface`](super::portal::PortalSurface)
+/// on both sides along their thinnest axis.
+///
+/// Authored in the glTF scene's extras, see [`insert_portalable`].
+#[derive(Debug, Default, Component, Reflect)]
+#[reflect(Component, Default)]
+pub struct Portalable;
+
+/// The portal surfaces of the [`Portalable`] node have been spawned.
+#[derive(Debug, Component)]
+pub struct PortalableSurfaces;
+
+pub(super) trait AppExt {
+    fn register_level_types(&mut self) -> &mut Self;
+}
+
+impl AppExt for App {
+    fn register_level_types(&mut self) -> &mut Self {
+        self.register_type::<Portalable>()
+    }
+}
+
+/// Spawns the first scene of the glTF file at the asset `path`.
+pub fn spawn_level(path: &str, asset_server: &AssetServer, commands: &mut Commands) -> Entity {
+    commands
+        .spawn((
+            Name::new(format!("Level {path}")),
+            SceneBundle {
+                scene: asset_server.load(GltfAssetLabel::Scene(0).from_asset(path.to_owned())),
+                ..Default::default()
+            },
+        ))
+        .id()
+}
+
+/// Inserts [`Portalable`] into the glTF nodes with a `Portalable` key in their extras.
+pub fn insert_portalable(
+    extras_q: Query<(Entity, &GltfExtras), Added<GltfExtras>>,
+    mut commands: Commands,
+) {
+    for (entity, extras) in extras_q.iter() {
+        let is_portalable =
+            serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&extras.value)
+                .is_ok_and(|extras| extras.contains_key("Portalable"));
+        if is_portalable {
+            commands.entity(entity).insert(Portalable);
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn spawn_portalable_surfaces(
+    portalable_q: Query<
+        (Entity, Option<&Handle<Mesh>>, Option<&Children>),
+        (With<Portalable>, Without<PortalableSurfaces>),
+    >,
+    mesh_q: Query<(&Handle<Mesh>, &Transform)>,
+    meshes: Res<Assets<Mesh>>,
+    mut commands: Commands,
+) {
+    'portalable: for (entity, mesh, children) in portalable_q.iter() {
+        // the glTF primitives are spawned as the children of the node
+        let child_meshes = children
+            .into_iter()
+            .flatten()
+            .filter_map(|&child| mesh_q.get(child).ok())
+            .map(|(mesh, &transform)| (mesh, transform));
+
+        let mut bounds: Option<(Vec3, Vec3)> = None;
+        for (mesh, transform) in mesh
+            .map(|mesh| (mesh, Transform::IDENTITY))
+            .into_iter()
+            .chain(child_meshes)
+        {
+            let Some(aabb) = meshes.get(mesh).and_then(Mesh::compute_aabb) else {
+                // the mesh is not loaded yet, so try again on the next frame
+                continue 'portalable;
+            };
+            let (a, b) = (
+                transform.transform_point(aabb.min().into()),
+                transform.transform_point(aabb.max().into()),
+            );
+            let (min, max) = (a.min(b), a.max(b));
+            bounds = Some(match bounds {
+                Some((bounds_min, bounds_max)) => (bounds_min.min(min), bounds_max.max(max)),
+                None => (min, max),
+            });
+        }
+
+        let Some((min, max)) = bounds else {
+            continue;
+        };
+
+        commands
+            .entity(entity)
+            .insert(PortalableSurfaces)
+            .with_children(|child| {
+                for (size, transform) in portalable_surfaces(min, max) {
+                    child.spawn(portal_surface_bundle(size, transform));
+                }
+            });
+    }
+}
+
+/// The surfaces on both sides of the bounding box along its thinnest axis.
+fn portalable_surfaces(min: Vec3, max: Vec3) -> impl Iterator<Item = (Vec2, Transform)> {
+    let center = (min + max) / 2.;
+    let extents = max - min;
+    let axis = if extents.x <= extents.y && extents.x <= extents.z {
+        Vec3::X
+    } else if extents.y <= extents.z {
+        Vec3::Y
+    } else {
+        Vec3::Z
+    };
+
+    [axis, -axis].into_iter().map(move |normal| {
+        let up = if normal.y.abs() > 0.5 {
+            Vec3::NEG_Z
+        } else {
+            Vec3::Y
+        };
+        let transform =
+            Transform::from_translation(center + normal * extents / 2.).looking_to(-normal, up);
+        let size = Vec2::new(
+            (transform.rotation * Vec3::X).abs().dot(extents),
+            (transform.rotation * Vec3::Y).abs().dot(extents),
+        );
+        (size, transform)
+    })
+}
@@ -2,6 +2,7 @@ use bevy::app::App;
 
 pub mod debug_info;
 pub mod input;
+pub mod level;
 pub mod player;
 pub mod portal;
 pub mod scene;
@@ -20,10 +21,13 @@ pub trait AppExt {
 
 impl AppExt for App {
     fn register_types(&mut self) -> &mut Self {
+        use level::AppExt as LevelAppExt;
         use player::AppExt as PlayerAppExt;
         use portal::AppExt as PortalAppExt;
 
-        self.register_player_types().register_portal_types()
+        self.register_player_types()
+            .register_portal_types()
+            .register_level_types()
     }
 
     fn _seal(_seal: seal::Seal) {}
@@ -2,9 +2,12 @@
 
 use bevy::{
     color::palettes,
-    input::mouse::MouseMotion,
     prelude::*,
-    render::view::{Layer, RenderLayers},
+    render::{
+        camera::Viewport,
+        view::{Layer, RenderLayers},
+    },
+    window::PrimaryWindow,
 };
 #[cfg(feature = "debug")]
 use bevy_editor_pls::default_windows::cameras::EDITOR_RENDER_LAYER;
@@ -16,12 +19,26 @@ use bevy_tnua::{
 use bevy_tnua_rapier3d::{TnuaRapier3dIOBundle, TnuaRapier3dSensorShape};
 
 use crate::{
-    resource::{Controls, Fov, MouseSensitivity},
+    domain::portal::{
+        portal_render_layer, slicing::PortalTraveller, traversal::PortalCrossing, PortalGun,
+        PortalPairId, PortalPairInfo, PortalPairs, PORTAL_RENDER_LAYER,
+    },
+    physics::PhysicsInterpolation,
+    resource::{Controls, ControlsConfig, Fov, LocalPlayers, MouseSensitivity},
     ExpDecay, ALL_RENDER_LAYERS,
 };
 
+/// The render layer of the first player, the others follow it.
 pub const PLAYER_RENDER_LAYER: Layer = 1;
 pub const PLAYER_COLLISION_GROUP: Group = Group::GROUP_2;
+pub const MAX_LOCAL_PLAYERS: usize = 4;
+
+/// The portal colors of the players after the first one, who uses the default pair.
+const CO_OP_PORTAL_COLORS: [(Srgba, Srgba); MAX_LOCAL_PLAYERS - 1] = [
+    (palettes::css::ORANGE, palettes::css::DODGER_BLUE),
+    (palettes::css::LIME, palettes::css::MAGENTA),
+    (palettes::css::GOLD, palettes::css::TEAL),
+];
 
 /// m
 const PLAYER_HEIGHT: f32 = 1.75;
@@ -39,7 +56,10 @@ const JUMP_HEIGHT: f32 = 3.;
 
 #[derive(Debug, Default, Component, Reflect)]
 #[reflect(Component)]
-pub struct Player;
+pub struct Player {
+    /// The player's place among the [`LocalPlayers`].
+    pub index: usize,
+}
 
 #[derive(Default, Component, Reflect)]
 #[reflect(Component, Default)]
@@ -61,9 +81,17 @@ impl Velocity {
     }
 }
 
+/// The camera of the [`Player`] with the same `index`.
 #[derive(Debug, Default, Component, Reflect)]
 #[reflect(Component, Default)]
-pub struct PlayerCamera;
+pub struct PlayerCamera {
+    pub index: usize,
+}
+
+/// The render layer of the player's body, which is hidden from the player's own camera.
+pub fn player_render_layer(index: usize) -> Layer {
+    PLAYER_RENDER_LAYER + index
+}
 
 pub(super) trait AppExt {
     fn register_player_types(&mut self) -> &mut Self;
@@ -78,17 +106,63 @@ impl AppExt for App {
     }
 }
 
+/// Spawns the [`LocalPlayers`], and returns their cameras.
 pub fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    mut portal_pairs: ResMut<PortalPairs>,
     fov: Res<Fov>,
+    local_players: Res<LocalPlayers>,
+) -> Vec<Entity> {
+    (0..local_players.0.clamp(1, MAX_LOCAL_PLAYERS))
+        .map(|index| {
+            let pair = match index.checked_sub(1) {
+                None => PortalPairId::DEFAULT,
+                Some(co_op_index) => {
+                    let (primary_color, secondary_color) = CO_OP_PORTAL_COLORS[co_op_index];
+                    portal_pairs.register(PortalPairInfo {
+                        name: format!("Player {} Portal", index + 1),
+                        primary_color: primary_color.into(),
+                        secondary_color: secondary_color.into(),
+                    })
+                }
+            };
+            spawn_player(
+                index,
+                pair,
+                &mut commands,
+                &mut meshes,
+                &mut materials,
+                &fov,
+            )
+        })
+        .collect()
+}
+
+fn spawn_player(
+    index: usize,
+    pair: PortalPairId,
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    fov: &Fov,
 ) -> Entity {
+    // the player sees the others, and only its own portal faces
+    let render_layers = ALL_RENDER_LAYERS
+        .clone()
+        .without(player_render_layer(index))
+        .without(PORTAL_RENDER_LAYER)
+        .with(portal_render_layer(index));
     let camera = commands
         .spawn((
-            Name::new("FPS Camera"),
-            PlayerCamera,
+            Name::new(format!("FPS Camera {index}")),
+            PlayerCamera { index },
             Camera3dBundle {
+                camera: Camera {
+                    order: index as isize,
+                    ..Default::default()
+                },
                 transform: Transform::from_xyz(0., EYES_HEIGHT / 2., 0.),
                 projection: PerspectiveProjection {
                     fov: fov.radians(),
@@ -99,27 +173,34 @@ pub fn setup(
                 ..Default::default()
             },
             #[cfg(feature = "debug")]
-            ALL_RENDER_LAYERS
-                .clone()
-                .without(PLAYER_RENDER_LAYER)
-                .without(EDITOR_RENDER_LAYER as Layer),
+            render_layers.without(EDITOR_RENDER_LAYER as Layer),
             #[cfg(not(feature = "debug"))]
-            ALL_RENDER_LAYERS.clone().without(PLAYER_RENDER_LAYER),
+            render_layers,
         ))
         .id();
     commands
         .spawn((
-            Name::new("Player"),
-            Player,
+            Name::new(format!("Player {index}")),
+            Player { index },
             Grounded::default(),
             Velocity::default(),
+            PortalCrossing::default(),
+            // turned by the look controls every frame
+            PhysicsInterpolation::translation_only(),
+            PortalGun {
+                pair,
+                ..Default::default()
+            },
+            PortalTraveller,
+            ControlsConfig::for_player(index),
+            Controls::default(),
             PbrBundle {
                 mesh: meshes.add(Mesh::from(Capsule3d::new(
                     PLAYER_RADIUS,
                     PLAYER_HEIGHT - 2. * PLAYER_RADIUS,
                 ))),
                 material: materials.add(Color::Srgba(palettes::basic::AQUA)),
-                transform: Transform::from_xyz(0., PLAYER_HEIGHT, 0.),
+                transform: Transform::from_xyz(index as f32 * 2., PLAYER_HEIGHT, 0.),
                 ..Default::default()
             },
             RigidBody::Dynamic,
@@ -131,68 +212,98 @@ pub fn setup(
             TnuaRapier3dSensorShape(Collider::cylinder(0., PLAYER_RADIUS - 0.01)),
             CollisionGroups::new(PLAYER_COLLISION_GROUP, Group::all()),
             #[cfg(feature = "debug")]
-            RenderLayers::from_layers(&[PLAYER_RENDER_LAYER, EDITOR_RENDER_LAYER]),
+            RenderLayers::from_layers(&[player_render_layer(index), EDITOR_RENDER_LAYER]),
             #[cfg(not(feature = "debug"))]
-            const { RenderLayers::layer(PLAYER_RENDER_LAYER) },
+            RenderLayers::layer(player_render_layer(index)),
         ))
         .add_child(camera);
 
     camera
 }
 
-pub fn movement(
-    controls: Res<Controls>,
-    mut player_q: Query<(&GlobalTransform, &mut TnuaController), With<Player>>,
+/// Splits the window into side by side viewports of the player cameras.
+pub fn split_screen(
+    window_q: Query<&Window, With<PrimaryWindow>>,
+    mut camera_q: Query<(&PlayerCamera, &mut Camera)>,
 ) {
-    let Ok((player_gt, mut controller)) = player_q.get_single_mut() else {
+    let Ok(window) = window_q.get_single() else {
         return;
     };
+    let count = camera_q.iter().count() as u32;
+    if count <= 1 {
+        return;
+    }
+
+    let size = UVec2::new(window.physical_width() / count, window.physical_height());
+    for (&PlayerCamera { index }, mut camera) in camera_q.iter_mut() {
+        let position = UVec2::new(size.x * index as u32, 0);
+        if camera
+            .viewport
+            .as_ref()
+            .map(|viewport| (viewport.physical_position, viewport.physical_size))
+            != Some((position, size))
+        {
+            camera.viewport = Some(Viewport {
+                physical_position: position,
+                physical_size: size,
+                ..Default::default()
+            });
+        }
+    }
+}
 
-    let rotation_angle = Quat::from_affine3(&player_gt.affine())
-        .to_euler(EulerRot::YXZ)
-        .0;
-    let speed = if controls.run { RUN_SPEED } else { WALK_SPEED };
-    let desired_velocity = controls
-        .to_direction()
-        .rotate(Vec2::new(rotation_angle.cos(), -rotation_angle.sin()))
-        * speed;
-    let desired_velocity = Vec3::new(desired_velocity.x, 0., desired_velocity.y);
-
-    controller.basis(TnuaBuiltinWalk {
-        desired_velocity,
-        // acceleration: speed * 0.9,
-        float_height: PLAYER_HEIGHT / 2. + 0.005,
-        // air_acceleration: speed * 0.5,
-        ..Default::default()
-    });
-
-    if controls.jump {
-        controller.action(TnuaBuiltinJump {
-            height: JUMP_HEIGHT,
+pub fn movement(
+    mut player_q: Query<(&GlobalTransform, &Controls, &mut TnuaController), With<Player>>,
+) {
+    for (player_gt, controls, mut controller) in player_q.iter_mut() {
+        let rotation_angle = Quat::from_affine3(&player_gt.affine())
+            .to_euler(EulerRot::YXZ)
+            .0;
+        let speed = if controls.run { RUN_SPEED } else { WALK_SPEED };
+        let desired_velocity = controls
+            .to_direction()
+            .rotate(Vec2::new(rotation_angle.cos(), -rotation_angle.sin()))
+            * speed;
+        let desired_velocity = Vec3::new(desired_velocity.x, 0., desired_velocity.y);
+
+        controller.basis(TnuaBuiltinWalk {
+            desired_velocity,
+            // acceleration: speed * 0.9,
+            float_height: PLAYER_HEIGHT / 2. + 0.005,
+            // air_acceleration: speed * 0.5,
             ..Default::default()
         });
+
+        if controls.jump {
+            controller.action(TnuaBuiltinJump {
+                height: JUMP_HEIGHT,
+                ..Default::default()
+            });
+        }
     }
 }
 
 pub fn rotation(
     time: Res<Time>,
     sensitivity: Res<MouseSensitivity>,
-    mut input: EventReader<MouseMotion>,
-    mut player_q: Query<&mut Transform, (With<Player>, Without<PlayerCamera>)>,
+    mut player_q: Query<
+        (&mut Transform, &Controls, &Children),
+        (With<Player>, Without<PlayerCamera>),
+    >,
     mut camera_q: Query<&mut Transform, (With<PlayerCamera>, Without<Player>)>,
 ) {
-    let mut player_transform = player_q.single_mut();
-    let mut camera_transform = camera_q.single_mut();
-    let (mut camera_pitch, _, _) = camera_transform.rotation.to_euler(EulerRot::XYZ);
-
-    for MouseMotion {
-        delta: Vec2 { x, y },
-    } in input.read().copied()
-    {
+    for (mut player_transform, controls, children) in player_q.iter_mut() {
+        let mut cameras = camera_q.iter_many_mut(children);
+        let Some(mut camera_transform) = cameras.fetch_next() else {
+            continue;
+        };
+        let (camera_pitch, _, _) = camera_transform.rotation.to_euler(EulerRot::XYZ);
+        let Vec2 { x, y } = controls.look;
+
         player_transform.rotate_y(-x * time.delta_seconds() * sensitivity.0);
 
-        camera_pitch = (camera_pitch - y * time.delta_seconds() * sensitivity.0)
+        let camera_pitch = (camera_pitch - y * time.delta_seconds() * sensitivity.0)
             .clamp(-std::f32::consts::FRAC_PI_2, std::f32::consts::FRAC_PI_2);
+        camera_transform.rotation = Quat::from_rotation_x(camera_pitch);
     }
-    camera_transform.rotation = Quat::from_rotation_x(camera_pitch);
 }
@@ -3,7 +3,6 @@ use bevy::{
         core_3d::graph::Core3d,
         tonemapping::{DebandDither, Tonemapping},
     },
-    ecs::query::QuerySingleError,
     math::{AspectRatio, Vec3A},
     pbr::PbrProjectionPlugin,
     prelude::*,
@@ -11,32 +10,61 @@ use bevy::{
         camera::{CameraMainTextureUsages, CameraProjection, CameraRenderGraph, Exposure},
         primitives::Frustum,
         render_resource::{
-            AsBindGroup, Extent3d, ShaderRef, TextureDescriptor, TextureDimension, TextureFormat,
-            TextureUsages,
+            AsBindGroup, Extent3d, ShaderRef, ShaderType, TextureDescriptor, TextureDimension,
+            TextureFormat, TextureUsages,
         },
-        view::{ColorGrading, VisibleEntities},
+        view::{ColorGrading, Layer, RenderLayers, VisibilitySystems, VisibleEntities},
     },
-    window::{PrimaryWindow, WindowResized},
+    transform::helper::TransformHelper,
+    utils::HashMap,
+    window::PrimaryWindow,
 };
 #[cfg(feature = "debug")]
 use bevy_editor_pls::default_windows::cameras::EDITOR_RENDER_LAYER;
 use bevy_rapier3d::{
-    geometry::{CollisionGroups, Group},
+    geometry::{ActiveHooks, CollisionGroups, Group},
     pipeline::QueryFilter,
-    plugin::RapierContext,
+    plugin::PhysicsSet,
 };
+use cfg_if::cfg_if;
 
+use crate::ALL_RENDER_LAYERS;
 use crate::{
     domain::{debug_info, input, player::PLAYER_COLLISION_GROUP},
+    physics::{self, PhysicsSchedule},
     resource::{Controls, Fov},
 };
-#[cfg(feature = "debug")]
-use crate::ALL_RENDER_LAYERS;
 
-use super::player::PlayerCamera;
+use super::player::{Player, PlayerCamera};
+use projectile::PortalProjectile;
+
+pub mod collision;
+pub mod fizzler;
+pub mod placement;
+pub mod projectile;
+pub mod raycast;
+pub mod slicing;
+pub mod traversal;
+pub mod visibility;
 
 pub const DEFAULT_PORTAL_SIZE: Vec2 = Vec2::new(1., 2.);
+pub const DEFAULT_PORTAL_RECURSION_DEPTH: usize = 3;
 pub const PORTAL_RAY_COLLISION_GROUP: Group = Group::GROUP_5;
+/// How many portals the portal gun can shoot through
+pub const PORTAL_GUN_MAX_HOPS: usize = 4;
+/// m/s
+pub const DEFAULT_PORTAL_PROJECTILE_SPEED: f32 = 40.;
+/// m
+pub const PORTAL_PROJECTILE_RANGE: f32 = 200.;
+/// The number of steps the portal view resolution is quantized into,
+/// see [`PortalViewResolution`].
+pub const PORTAL_VIEW_RESOLUTION_BUCKETS: u32 = 8;
+/// The portals closer than this are rendered at the full resolution regardless of their size, m
+pub const PORTAL_VIEW_FULL_RESOLUTION_DISTANCE: f32 = 4.;
+/// s
+pub const PORTAL_OPENING_DURATION: f32 = 0.3;
+/// s
+pub const PORTAL_CLOSING_DURATION: f32 = 0.2;
 
 #[derive(Debug, Default, Component, Reflect)]
 #[reflect(Component)]
@@ -44,20 +72,422 @@ pub struct PortalSurface {
     pub size: Vec2,
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+pub enum PortalOutline {
+    #[default]
+    Rectangle,
+    Ellipse,
+}
+
+/// The opening of the portal, on its local XY plane.
+#[derive(Debug, Clone, Copy, PartialEq, Component, Reflect)]
+#[reflect(Component, Default)]
+pub struct PortalShape {
+    /// m
+    pub size: Vec2,
+    pub outline: PortalOutline,
+}
+
+impl Default for PortalShape {
+    fn default() -> Self {
+        Self {
+            size: DEFAULT_PORTAL_SIZE,
+            outline: PortalOutline::Rectangle,
+        }
+    }
+}
+
+impl PortalShape {
+    pub fn ellipse(size: Vec2) -> Self {
+        Self {
+            size,
+            outline: PortalOutline::Ellipse,
+        }
+    }
+
+    pub fn half_size(self) -> Vec2 {
+        self.size / 2.
+    }
+
+    /// The shape with the outline pushed out by the `margin`.
+    pub fn grown(self, margin: f32) -> Self {
+        Self {
+            size: self.size + 2. * margin,
+            ..self
+        }
+    }
+
+    /// Whether the `point` on the portal's plane is within the opening.
+    pub fn contains(self, point: Vec2) -> bool {
+        match self.outline {
+            PortalOutline::Rectangle => point.abs().cmple(self.half_size()).all(),
+            PortalOutline::Ellipse => (point / self.half_size()).length_squared() <= 1.,
+        }
+    }
+
+    /// The corners of the largest rectangle inscribed into the opening.
+    pub fn inner_corners(self) -> [Vec2; 4] {
+        let half_size = match self.outline {
+            PortalOutline::Rectangle => self.half_size(),
+            PortalOutline::Ellipse => self.half_size() * std::f32::consts::FRAC_1_SQRT_2,
+        };
+        [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)].map(|(x, y)| Vec2::new(x, y) * half_size)
+    }
+
+    /// The corners of the bounding rectangle of the opening.
+    pub fn outer_corners(self) -> [Vec2; 4] {
+        [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)]
+            .map(|(x, y)| Vec2::new(x, y) * self.half_size())
+    }
+
+    /// Faces the portal's front, +Z.
+    pub fn mesh(self) -> Mesh {
+        let half_size = self.half_size();
+        match self.outline {
+            PortalOutline::Rectangle => Plane3d::new(Vec3::Z, half_size).into(),
+            PortalOutline::Ellipse => Ellipse::new(half_size.x, half_size.y).into(),
+        }
+    }
+}
+
+/// The mesh of the portal it is a child of, as seen by the
+/// [`PlayerCamera`] with the same index as the `viewer`.
+///
+/// It shows the view of the portal camera at level `0` of the same `viewer`.
+#[derive(Debug, Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct PortalFace {
+    pub viewer: usize,
+}
+
+/// Renders the view through the portal it is a child of for the `viewer`.
+///
+/// The camera at level `0` looks through the portal as seen by the player,
+/// and the camera at level `k + 1` looks through the portal as seen by the camera at level `k`.
 #[derive(Debug, Default, Component, Reflect)]
 #[reflect(Component)]
-pub struct PortalCamera;
+pub struct PortalCamera {
+    pub viewer: usize,
+    pub level: usize,
+}
+
+/// A copy of the portal's mesh, visible only to the portal cameras
+/// of the same `viewer` at the same `level`.
+#[derive(Debug, Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct PortalProxy {
+    pub viewer: usize,
+    pub level: usize,
+}
+
+/// The number of times the portal views are rendered through other portals.
+#[derive(Debug, Clone, Copy, Resource)]
+pub struct PortalRecursionDepth(pub usize);
+
+impl Default for PortalRecursionDepth {
+    fn default() -> Self {
+        Self(DEFAULT_PORTAL_RECURSION_DEPTH)
+    }
+}
+
+/// How the resolutions of the portal views follow the portals' sizes on the screen.
+#[derive(Debug, Clone, Copy, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct PortalViewResolutionPolicy {
+    /// Multiplies the resolution of every portal view before it is clamped.
+    ///
+    /// Defaults to `1`, the viewport's resolution for a portal spanning the viewport.
+    pub quality: f32,
+    /// The smallest fraction of the viewport's resolution.
+    pub min_scale: f32,
+    /// The largest fraction of the viewport's resolution.
+    pub max_scale: f32,
+}
+
+impl Default for PortalViewResolutionPolicy {
+    fn default() -> Self {
+        Self {
+            quality: 1.,
+            min_scale: 1. / PORTAL_VIEW_RESOLUTION_BUCKETS as f32,
+            max_scale: 1.,
+        }
+    }
+}
+
+impl PortalViewResolutionPolicy {
+    /// The bucket of the portal, based on its size in the normalized device coordinates
+    /// of the viewer's camera and its distance to it.
+    fn bucket(&self, ndc_size: f32, distance: f32) -> u32 {
+        let closeness = (PORTAL_VIEW_FULL_RESOLUTION_DISTANCE / distance).min(1.);
+        let scale = (self.quality * ndc_size.max(closeness))
+            .clamp(self.min_scale, self.max_scale.max(self.min_scale));
+        ((scale * PORTAL_VIEW_RESOLUTION_BUCKETS as f32).ceil() as u32)
+            .clamp(1, PORTAL_VIEW_RESOLUTION_BUCKETS)
+    }
+}
+
+/// The resolution of the views of the [`PortalFace`]'s viewer,
+/// in `1 / PORTAL_VIEW_RESOLUTION_BUCKETS` fractions of the viewer's viewport.
+#[derive(Debug, Clone, Copy, Component, Reflect)]
+#[reflect(Component)]
+pub struct PortalViewResolution {
+    pub bucket: u32,
+    /// The physical size of the viewer's viewport the views were allocated for.
+    pub viewport_size: UVec2,
+}
+
+impl PortalViewResolution {
+    pub fn full(viewport_size: UVec2) -> Self {
+        Self {
+            bucket: PORTAL_VIEW_RESOLUTION_BUCKETS,
+            viewport_size,
+        }
+    }
+
+    pub fn extent(self) -> Extent3d {
+        let scale = self.bucket as f32 / PORTAL_VIEW_RESOLUTION_BUCKETS as f32;
+        let size = (self.viewport_size.as_vec2() * scale)
+            .as_uvec2()
+            .max(UVec2::ONE);
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            ..Default::default()
+        }
+    }
+}
+
+/// The render layer of the portals seen by the first player.
+pub const PORTAL_RENDER_LAYER: Layer = 32;
+/// The number of render layers reserved for the portals of each viewer,
+/// which also limits the recursion depth.
+pub const PORTAL_RENDER_LAYERS_PER_VIEWER: Layer = 16;
+
+/// The render layer of the [`PortalFace`]s seen by the `viewer`.
+pub fn portal_render_layer(viewer: usize) -> Layer {
+    PORTAL_RENDER_LAYER + viewer * PORTAL_RENDER_LAYERS_PER_VIEWER
+}
+
+/// The render layer of the portal proxies seen by the portal cameras
+/// of the `viewer` at the `level`.
+pub fn portal_view_render_layer(viewer: usize, level: usize) -> Layer {
+    portal_render_layer(viewer) + 1 + level
+}
+
+fn portal_camera_render_layers(viewer: usize, level: usize) -> RenderLayers {
+    let rl = ALL_RENDER_LAYERS
+        .clone()
+        .without(PORTAL_RENDER_LAYER)
+        .with(portal_view_render_layer(viewer, level));
+
+    cfg_if! {
+        if #[cfg(feature = "debug")] {
+            rl.without(EDITOR_RENDER_LAYER)
+        } else {
+            rl
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+pub enum PortalState {
+    /// Growing out of the surface for [`PORTAL_OPENING_DURATION`].
+    #[default]
+    Opening,
+    /// The view through the portal is rendered, and it can be traversed.
+    Open,
+    /// Shrinking back into the surface for [`PORTAL_CLOSING_DURATION`].
+    Closing,
+    /// Despawned on the next update.
+    Closed,
+}
 
 #[derive(Debug, Clone, Copy, Default, Component, Reflect)]
 #[reflect(Component)]
 pub struct Portal {
     pair: Option<Entity>,
+    wall: Option<Entity>,
+    state: PortalState,
+    /// The time spent in the current `state`, s
+    state_time: f32,
+}
+
+impl Portal {
+    pub fn pair(&self) -> Option<Entity> {
+        self.pair
+    }
+
+    /// The collider which owns the [`PortalSurface`] the portal is placed onto.
+    pub fn wall(&self) -> Option<Entity> {
+        self.wall
+    }
+
+    pub fn state(&self) -> PortalState {
+        self.state
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state == PortalState::Open
+    }
+
+    /// Whether the portal is on its way out, and a new one may take its channel.
+    pub fn is_closing(&self) -> bool {
+        matches!(self.state, PortalState::Closing | PortalState::Closed)
+    }
+
+    /// Starts closing the portal, from however far it has opened.
+    pub fn close(&mut self) {
+        if !self.is_closing() {
+            self.state_time = (1. - self.openness()) * PORTAL_CLOSING_DURATION;
+            self.state = PortalState::Closing;
+        }
+    }
+
+    /// How far the portal has opened, from `0` when closed to `1` when open.
+    pub fn openness(&self) -> f32 {
+        match self.state {
+            PortalState::Opening => (self.state_time / PORTAL_OPENING_DURATION).min(1.),
+            PortalState::Open => 1.,
+            PortalState::Closing => 1. - (self.state_time / PORTAL_CLOSING_DURATION).min(1.),
+            PortalState::Closed => 0.,
+        }
+    }
+}
+
+/// Maps world space in front of the `portal` to world space behind its `pair`:
+/// `pair × 180° Y rotation × portal⁻¹`.
+pub fn portal_transfer_matrix(portal: &GlobalTransform, pair: &GlobalTransform) -> Mat4 {
+    pair.compute_matrix()
+        * Mat4::from_rotation_translation(
+            Quat::from_axis_angle(Vec3::Y, std::f32::consts::PI),
+            Vec3::ZERO,
+        )
+        * portal.compute_matrix().inverse()
+}
+
+/// Identifies a pair of linked portals.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Reflect)]
+pub struct PortalPairId(pub u32);
+
+impl PortalPairId {
+    /// The pair of [`Portal1`] and [`Portal2`], registered by the [`PortalPlugin`].
+    pub const DEFAULT: Self = Self(0);
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Reflect)]
+pub enum PortalEnd {
+    #[default]
+    Primary,
+    Secondary,
 }
 
-pub trait PortalKind: Component + Copy {
+impl PortalEnd {
+    pub fn other(self) -> Self {
+        match self {
+            Self::Primary => Self::Secondary,
+            Self::Secondary => Self::Primary,
+        }
+    }
+}
+
+/// Which end of which pair the portal is.
+///
+/// There is at most one portal per channel, and it is linked to the portal at the other end.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Component, Reflect)]
+#[reflect(Component)]
+pub struct PortalChannel {
+    pub pair: PortalPairId,
+    pub end: PortalEnd,
+}
+
+impl PortalChannel {
+    pub fn new(pair: PortalPairId, end: PortalEnd) -> Self {
+        Self { pair, end }
+    }
+
+    pub fn partner(self) -> Self {
+        Self {
+            end: self.end.other(),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PortalPairInfo {
+    pub name: String,
+    pub primary_color: Color,
+    pub secondary_color: Color,
+}
+
+impl PortalPairInfo {
+    pub fn color(&self, end: PortalEnd) -> Color {
+        match end {
+            PortalEnd::Primary => self.primary_color,
+            PortalEnd::Secondary => self.secondary_color,
+        }
+    }
+}
+
+/// The registry of all the portal pairs which may coexist at runtime.
+#[derive(Debug, Resource)]
+pub struct PortalPairs {
+    pairs: HashMap<PortalPairId, PortalPairInfo>,
+    next_id: u32,
+}
+
+impl Default for PortalPairs {
+    fn default() -> Self {
+        let mut pairs = Self {
+            pairs: HashMap::new(),
+            next_id: 0,
+        };
+        pairs.register(PortalPairInfo {
+            name: "Portal".to_owned(),
+            primary_color: Portal1::color(),
+            secondary_color: Portal2::color(),
+        });
+        pairs
+    }
+}
+
+impl PortalPairs {
+    pub fn register(&mut self, info: PortalPairInfo) -> PortalPairId {
+        let id = PortalPairId(self.next_id);
+        self.next_id += 1;
+        self.pairs.insert(id, info);
+        id
+    }
+
+    pub fn get(&self, id: PortalPairId) -> Option<&PortalPairInfo> {
+        self.pairs.get(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (PortalPairId, &PortalPairInfo)> {
+        self.pairs.iter().map(|(&id, info)| (id, info))
+    }
+
+    pub fn color(&self, channel: PortalChannel) -> Color {
+        self.get(channel.pair)
+            .map_or(Color::WHITE, |info| info.color(channel.end))
+    }
+
+    pub fn name(&self, channel: PortalChannel) -> String {
+        let pair_name = self.get(channel.pair).map_or("Portal", |info| &info.name);
+        match channel.end {
+            PortalEnd::Primary => format!("{pair_name} 1"),
+            PortalEnd::Secondary => format!("{pair_name} 2"),
+        }
+    }
+}
+
+/// A compile-time name for one of the ends of a pre-registered pair.
+///
+/// The marker component is inserted into the portal spawned at its [`PortalKind::CHANNEL`].
+pub trait PortalKind: Component + Copy + Default {
     type Pair: PortalKind<Pair = Self>;
 
-    fn new() -> Self;
+    const CHANNEL: PortalChannel;
 
     fn color() -> Color;
 }
@@ -73,9 +503,10 @@ pub struct Portal2;
 impl PortalKind for Portal1 {
     type Pair = Portal2;
 
-    fn new() -> Self {
-        Self
-    }
+    const CHANNEL: PortalChannel = PortalChannel {
+        pair: PortalPairId::DEFAULT,
+        end: PortalEnd::Primary,
+    };
 
     fn color() -> Color {
         Color::srgb(0.6, 0.7, 0.8)
@@ -85,15 +516,53 @@ impl PortalKind for Portal1 {
 impl PortalKind for Portal2 {
     type Pair = Portal1;
 
-    fn new() -> Self {
-        Self
-    }
+    const CHANNEL: PortalChannel = PortalChannel {
+        pair: PortalPairId::DEFAULT,
+        end: PortalEnd::Secondary,
+    };
 
     fn color() -> Color {
         Color::srgb(0.8, 0.7, 0.6)
     }
 }
 
+/// Shoots the portals of the `pair`.
+#[derive(Debug, Clone, Copy, Default, Component, Reflect)]
+#[reflect(Component, Default)]
+pub struct PortalGun {
+    pub pair: PortalPairId,
+    pub mode: PortalGunMode,
+    /// The shape of the portals it places.
+    pub shape: PortalShape,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Reflect)]
+pub enum PortalGunMode {
+    /// The portal is placed instantly where the player is looking.
+    #[default]
+    Hitscan,
+    /// Fires a [`PortalProjectile`], which places the portal where it hits.
+    ///
+    /// The shots at a speed which is not positive miss right away.
+    Projectile {
+        /// m/s
+        speed: f32,
+    },
+}
+
+impl PortalGunMode {
+    pub fn projectile() -> Self {
+        Self::Projectile {
+            speed: DEFAULT_PORTAL_PROJECTILE_SPEED,
+        }
+    }
+
+    /// `None` unless the `speed` is positive, m/s
+    pub fn projectile_with_speed(speed: f32) -> Option<Self> {
+        (speed > 0.).then_some(Self::Projectile { speed })
+    }
+}
+
 pub(super) trait AppExt {
     fn register_portal_types(&mut self) -> &mut Self;
 }
@@ -102,119 +571,154 @@ impl AppExt for App {
     fn register_portal_types(&mut self) -> &mut Self {
         self.register_type::<PortalSurface>()
             .register_type::<Portal>()
+            .register_type::<PortalFace>()
+            .register_type::<PortalShape>()
+            .register_type::<PortalChannel>()
+            .register_type::<PortalGun>()
+            .register_type::<PortalViewResolution>()
+            .register_type::<visibility::PortalVisibility>()
+            .register_type::<PortalViewResolutionPolicy>()
+            .register_type::<PortalProjectile>()
+            .register_type::<traversal::PortalCrossing>()
+            .register_type::<slicing::PortalTraveller>()
+            .register_type::<slicing::PortalCloneOf>()
+            .register_type::<slicing::TravellerClone>()
+            .register_type::<fizzler::PortalFizzler>()
+            .register_type::<fizzler::Dissolvable>()
             .register_type::<Portal1>()
             .register_type::<Portal2>()
     }
 }
 
+/// Spawns a portal at the `channel`, closing the portal which is already there.
 #[derive(Debug, Clone, Copy, Event)]
-pub struct SpawnPortal<P: PortalKind> {
-    portal_kind: P,
-    transform: Transform,
+pub struct SpawnPortal {
+    pub channel: PortalChannel,
+    pub transform: Transform,
+    pub shape: PortalShape,
+    /// [`PortalState::Opening`], unless the portal is restored as it was.
+    pub state: PortalState,
+    /// The [`PortalSurface`] the portal is placed onto.
+    ///
+    /// The portal becomes its child, so that it follows the surface when it moves.
+    pub surface: Option<Entity>,
+    /// The collider which owns the [`PortalSurface`] the portal is placed onto.
+    ///
+    /// The travellers pass through it within the portal's opening, see [`collision`].
+    pub wall: Option<Entity>,
 }
 
-#[allow(clippy::type_complexity)]
-pub fn shoot_portal<P1: PortalKind<Pair = P2>, P2: PortalKind<Pair = P1>>(
-    controls: Res<Controls>,
+/// Closes the portal at the `channel`, if there is one, and leaves its partner unpaired.
+#[derive(Debug, Clone, Copy, Event)]
+pub struct ClosePortal {
+    pub channel: PortalChannel,
+}
+
+/// Shoots the portal gun of each player, which controls it, from the player's camera.
+#[allow(clippy::too_many_arguments)]
+pub fn shoot_portal(
+    gun_q: Query<(&PortalGun, &Controls, &Children), With<Player>>,
     camera_q: Query<(&Camera, &GlobalTransform), With<PlayerCamera>>,
-    portal_surface_q: Query<(&GlobalTransform, &PortalSurface)>,
-    rapier_ctx: Res<RapierContext>,
+    portal_raycast: raycast::PortalRaycast,
+    mut portal_placer: placement::PortalPlacer,
+    portal_pairs: Res<PortalPairs>,
+    mut projectile_assets: ResMut<projectile::PortalProjectileAssets>,
+    mut standard_materials: ResMut<Assets<StandardMaterial>>,
     mut commands: Commands,
 ) {
-    if !controls.shoot1 && !controls.shoot2 {
-        return;
-    }
-
-    println!("Shooting portal\n");
+    for (&PortalGun { pair, mode, shape }, controls, children) in gun_q.iter() {
+        if !controls.shoot1 && !controls.shoot2 {
+            continue;
+        }
 
-    let (camera, camera_transform) = camera_q.single();
-    let viewport_center = camera.logical_viewport_size().unwrap() / 2.;
+        let channel = if controls.shoot1 {
+            PortalChannel::new(pair, PortalEnd::Primary)
+        } else {
+            PortalChannel::new(pair, PortalEnd::Secondary)
+        };
 
-    let (ray_origin, ray_dir, max_toi) = {
-        // inlined body of `Camera::viewport_to_world`
-        let mut viewport_position = viewport_center;
+        let Some((camera, camera_transform)) = camera_q.iter_many(children).next() else {
+            continue;
+        };
         let Some(target_size) = camera.logical_viewport_size() else {
-            return;
+            continue;
         };
-        // Flip the Y co-ordinate origin from the top to the bottom.
-        viewport_position.y = target_size.y - viewport_position.y;
-        let ndc = viewport_position * 2. / target_size - Vec2::ONE;
-        let ndc_to_world = camera_transform.compute_matrix() * camera.clip_from_view().inverse();
-        let world_near_plane = ndc_to_world.project_point3(ndc.extend(1.));
-        // Using EPSILON because an ndc with Z = 0 returns NaNs.
-        let world_far_plane = ndc_to_world.project_point3(ndc.extend(f32::EPSILON));
-
-        let ray_line = world_far_plane - world_near_plane;
-        (world_near_plane, ray_line.normalize(), ray_line.length())
-    };
 
-    let Some((entity, distance)) = rapier_ctx.cast_ray(
-        ray_origin,
-        ray_dir,
-        max_toi,
-        true,
-        QueryFilter::new().groups(CollisionGroups::new(
-            PORTAL_RAY_COLLISION_GROUP,
-            PLAYER_COLLISION_GROUP.complement() & PORTAL_RAY_COLLISION_GROUP.complement(),
-        )),
-    ) else {
-        return;
-    };
-
-    let Ok((transform, &PortalSurface { size })) = portal_surface_q.get(entity) else {
-        return;
-    };
-
-    let point = ray_origin + ray_dir * distance;
-    println!("Intersect with plane {transform:?} at point {point}\n");
-
-    let point_on_plane = transform.affine().inverse().transform_point3(point).xy();
-    println!("Point on the plane: {point_on_plane}\n");
-
-    let half_size = size * transform.to_scale_rotation_translation().0.xy() / 2.;
+        let (ray_origin, ray_dir, max_toi) = {
+            // inlined body of `Camera::viewport_to_world`
+            let mut viewport_position = target_size / 2.;
+            // Flip the Y co-ordinate origin from the top to the bottom.
+            viewport_position.y = target_size.y - viewport_position.y;
+            let ndc = viewport_position * 2. / target_size - Vec2::ONE;
+            let ndc_to_world =
+                camera_transform.compute_matrix() * camera.clip_from_view().inverse();
+            let world_near_plane = ndc_to_world.project_point3(ndc.extend(1.));
+            // Using EPSILON because an ndc with Z = 0 returns NaNs.
+            let world_far_plane = ndc_to_world.project_point3(ndc.extend(f32::EPSILON));
+
+            let ray_line = world_far_plane - world_near_plane;
+            (world_near_plane, ray_line.normalize(), ray_line.length())
+        };
 
-    if point_on_plane.abs().cmpgt(half_size).any() {
-        // point is outside the portal surface
-        return;
+        match mode {
+            PortalGunMode::Hitscan => {
+                // the portals can be shot through the other portals
+                let path = portal_raycast.cast_ray(
+                    ray_origin,
+                    ray_dir,
+                    max_toi,
+                    true,
+                    portal_shot_filter(),
+                    PORTAL_GUN_MAX_HOPS,
+                );
+                let ray_dir = path.final_direction().unwrap_or(ray_dir);
+                portal_placer.place(channel, shape, path.hit, ray_dir);
+            }
+            PortalGunMode::Projectile { speed } if speed > 0. => {
+                commands.spawn(projectile::portal_projectile_bundle(
+                    PortalProjectile {
+                        channel,
+                        shape,
+                        velocity: ray_dir * speed,
+                        range_left: max_toi.min(PORTAL_PROJECTILE_RANGE),
+                    },
+                    ray_origin,
+                    portal_pairs.color(channel),
+                    &mut projectile_assets,
+                    &mut standard_materials,
+                ));
+            }
+            PortalGunMode::Projectile { .. } => {
+                // the projectile would never arrive, see `PortalGunMode::projectile_with_speed`
+                portal_placer.place(channel, shape, None, ray_dir);
+            }
+        }
     }
+}
 
-    let mut portal_transform = transform.compute_transform();
-    let clamped_point = transform.affine().transform_point3(
-        point_on_plane
-            .clamp(
-                -half_size + DEFAULT_PORTAL_SIZE / 2.,
-                half_size - DEFAULT_PORTAL_SIZE / 2.,
-            )
-            .extend(0.),
-    );
-    portal_transform.translation +=
-        (clamped_point - transform.translation()) + transform.back() * 0.01;
-
-    if controls.shoot1 {
-        commands.trigger(SpawnPortal {
-            portal_kind: P1::new(),
-            transform: portal_transform,
-        });
-    } else if controls.shoot2 {
-        commands.trigger(SpawnPortal {
-            portal_kind: P2::new(),
-            transform: portal_transform,
-        });
-    }
+/// The shots of the portal gun pass through the player.
+pub fn portal_shot_filter() -> QueryFilter<'static> {
+    QueryFilter::new().groups(CollisionGroups::new(
+        PORTAL_RAY_COLLISION_GROUP,
+        PLAYER_COLLISION_GROUP.complement() & PORTAL_RAY_COLLISION_GROUP.complement(),
+    ))
 }
 
 #[allow(clippy::too_many_arguments)]
-pub fn spawn_portal<P: PortalKind>(
-    spawn_portal: Trigger<SpawnPortal<P>>,
-    portal_q: Query<Entity, With<P>>,
-    mut pair_portal_q: Query<(Entity, &mut Portal), With<P::Pair>>,
+pub fn spawn_portal(
+    spawn_portal: Trigger<SpawnPortal>,
+    mut portal_q: Query<(Entity, &PortalChannel, &PortalShape, &mut Portal)>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut portal_view_materials: ResMut<Assets<PortalViewMaterial>>,
-    mut standard_materials: ResMut<Assets<StandardMaterial>>,
     mut images: ResMut<Assets<Image>>,
     fov: Res<Fov>,
-    mut portal_cam_q: Query<(&Parent, &mut Camera), With<PortalCamera>>,
+    portal_pairs: Res<PortalPairs>,
+    recursion_depth: Res<PortalRecursionDepth>,
+    portal_cam_q: Query<(&Parent, &PortalCamera, &Camera)>,
+    portal_face_q: Query<(Entity, &Parent, &PortalFace)>,
+    portal_proxy_q: Query<(Entity, &Parent, &PortalProxy)>,
+    player_cam_q: Query<(&PlayerCamera, &Camera)>,
     window_q: Query<&Window, With<PrimaryWindow>>,
 ) {
     let Ok(window) = window_q.get_single() else {
@@ -222,110 +726,338 @@ pub fn spawn_portal<P: PortalKind>(
     };
 
     let &SpawnPortal {
-        portal_kind,
+        channel,
         transform,
+        shape,
+        state,
+        surface,
+        wall,
     } = spawn_portal.event();
+    let name = portal_pairs.name(channel);
 
-    println!(
-        "Spawning {}",
-        std::any::type_name::<P>().rsplit_once("::").unwrap().1
-    );
-
-    match portal_q.get_single() {
-        Ok(portal) => commands.entity(portal).despawn_recursive(),
-        Err(err @ QuerySingleError::MultipleEntities(_)) => unreachable!("{err}"),
-        Err(QuerySingleError::NoEntities(_)) => {}
+    if let Some((.., mut portal)) = portal_q
+        .iter_mut()
+        .find(|(_, &portal_channel, _, portal)| portal_channel == channel && !portal.is_closing())
+    {
+        portal.close();
     }
 
-    let pair = pair_portal_q.iter_mut().next();
-
-    let portal_view_image = {
-        let size = Extent3d {
-            width: window.physical_width(),
-            height: window.physical_height(),
-            ..Default::default()
-        };
-        let mut img = Image {
-            texture_descriptor: TextureDescriptor {
-                label: None,
-                size,
-                dimension: TextureDimension::D2,
-                format: TextureFormat::Rgba8UnormSrgb,
-                mip_level_count: 1,
-                sample_count: 1,
-                usage: TextureUsages::TEXTURE_BINDING
-                    | TextureUsages::COPY_DST
-                    | TextureUsages::RENDER_ATTACHMENT,
-                view_formats: &[],
-            },
-            ..Default::default()
-        };
-        img.resize(size);
-        img
-    };
-
-    let portal_view_image_handle = images.add(portal_view_image);
+    let pair = portal_q
+        .iter_mut()
+        .find(|(_, &portal_channel, _, portal)| {
+            portal_channel == channel.partner() && !portal.is_closing()
+        })
+        .map(|(entity, _, &pair_shape, portal)| (entity, pair_shape, portal));
+
+    // each of the split screen viewers gets its own views, sized after its viewport
+    let depth = recursion_depth
+        .0
+        .clamp(1, PORTAL_RENDER_LAYERS_PER_VIEWER - 1);
+    let viewers = player_cam_q
+        .iter()
+        .map(|(&PlayerCamera { index }, camera)| {
+            let resolution = PortalViewResolution::full(camera.physical_viewport_size().unwrap_or(
+                UVec2::new(window.physical_width(), window.physical_height()),
+            ));
+            let views = (0..depth)
+                .map(|_| images.add(new_portal_view_image(resolution.extent())))
+                .collect::<Vec<_>>();
+            (index, resolution, views)
+        })
+        .collect::<Vec<_>>();
+
+    let portal_mesh = meshes.add(shape.mesh());
 
     let mut new_portal = commands.spawn((
-        Name::new(std::any::type_name::<P>().rsplit_once("::").unwrap().1),
+        Name::new(name),
         Portal {
-            pair: pair.iter().map(|(entity, _)| entity).copied().next(),
+            pair: pair.iter().map(|(entity, ..)| entity).copied().next(),
+            wall,
+            state,
+            ..Default::default()
+        },
+        channel,
+        shape,
+        visibility::PortalVisibility::default(),
+        TransformBundle {
+            local: transform,
+            // read by `set_parent_in_place` below, before the transforms are propagated
+            global: GlobalTransform::from(transform),
         },
-        portal_kind,
-        TransformBundle::from_transform(transform),
         VisibilityBundle::default(),
-        meshes.add(Plane3d::new(Vec3::Z, Vec2::new(0.5, 1.))),
+        // only for the bounds, the faces are rendered instead
+        portal_mesh.clone(),
     ));
+    let mut new_portal_faces = Vec::with_capacity(viewers.len());
     new_portal.with_children(|child| {
-        child.spawn((
-            Name::new("Portal Camera"),
-            PortalCamera,
-            PortalCamera3dBundle {
-                projection: PortalPerspectiveProjection {
-                    fov: fov.radians(),
-                    ..Default::default()
-                },
-                camera: Camera {
-                    order: -1,
-                    is_active: pair.is_some(),
-                    target: portal_view_image_handle.clone().into(),
-                    ..Default::default()
-                },
-                ..Default::default()
-            },
-            #[cfg(feature = "debug")]
-            ALL_RENDER_LAYERS.clone().without(EDITOR_RENDER_LAYER),
-        ));
+        for (viewer, resolution, views) in viewers {
+            let face = child
+                .spawn((
+                    Name::new(format!("Portal Face {viewer}")),
+                    PortalFace { viewer },
+                    resolution,
+                    TransformBundle::default(),
+                    VisibilityBundle::default(),
+                    portal_mesh.clone(),
+                    #[cfg(feature = "debug")]
+                    RenderLayers::from_layers(&[portal_render_layer(viewer), EDITOR_RENDER_LAYER]),
+                    #[cfg(not(feature = "debug"))]
+                    RenderLayers::layer(portal_render_layer(viewer)),
+                ))
+                .id();
+
+            let mut proxies = Vec::with_capacity(views.len());
+            for (level, view) in views.iter().enumerate() {
+                child.spawn((
+                    Name::new(format!("Portal Camera {viewer}.{level}")),
+                    PortalCamera { viewer, level },
+                    PortalCamera3dBundle {
+                        projection: PortalPerspectiveProjection {
+                            fov: fov.radians(),
+                            ..Default::default()
+                        },
+                        camera: Camera {
+                            // deeper levels are rendered first
+                            order: -1 - level as isize,
+                            // see `activate_portal_cameras`
+                            is_active: false,
+                            target: view.clone().into(),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    },
+                    portal_camera_render_layers(viewer, level),
+                ));
+
+                let proxy = child
+                    .spawn((
+                        Name::new(format!("Portal Proxy {viewer}.{level}")),
+                        PortalProxy { viewer, level },
+                        TransformBundle::default(),
+                        VisibilityBundle::default(),
+                        portal_mesh.clone(),
+                        RenderLayers::none().with(portal_view_render_layer(viewer, level)),
+                    ))
+                    .id();
+                proxies.push((proxy, level));
+            }
+
+            new_portal_faces.push((viewer, face, proxies, views));
+        }
     });
+    if let Some(surface) = surface {
+        // keeps the world pose
+        new_portal.set_parent_in_place(surface);
+    }
+    let new_portal = new_portal.id();
+
+    if let Some(wall) = wall {
+        commands
+            .entity(wall)
+            .insert(ActiveHooks::MODIFY_SOLVER_CONTACTS);
+    }
 
     match pair {
-        Some((pair_entity, mut pair_portal)) => {
-            _ = pair_portal.pair.replace(new_portal.id());
+        Some((pair_entity, pair_shape, mut pair_portal)) => {
+            _ = pair_portal.pair.replace(new_portal);
+
+            for (viewer, face, proxies, views) in new_portal_faces {
+                insert_portal_view_materials(
+                    &mut commands,
+                    face,
+                    proxies,
+                    &views,
+                    &mut portal_view_materials,
+                    PortalViewSettings::new(portal_pairs.color(channel), shape),
+                );
+
+                let Some((pair_face, ..)) = portal_face_q
+                    .iter()
+                    .find(|(_, parent, face)| parent.get() == pair_entity && face.viewer == viewer)
+                else {
+                    continue;
+                };
+
+                let mut pair_views = portal_cam_q
+                    .iter()
+                    .filter(|(parent, cam, _)| parent.get() == pair_entity && cam.viewer == viewer)
+                    .map(|(_, &PortalCamera { level, .. }, cam)| {
+                        (level, cam.target.as_image().unwrap().to_owned())
+                    })
+                    .collect::<Vec<_>>();
+                pair_views.sort_by_key(|&(level, _)| level);
+                let pair_views = pair_views
+                    .into_iter()
+                    .map(|(_, view)| view)
+                    .collect::<Vec<_>>();
+
+                insert_portal_view_materials(
+                    &mut commands,
+                    pair_face,
+                    portal_proxy_q
+                        .iter()
+                        .filter(|(_, parent, proxy)| {
+                            parent.get() == pair_entity && proxy.viewer == viewer
+                        })
+                        .map(|(proxy, _, &PortalProxy { level, .. })| (proxy, level)),
+                    &pair_views,
+                    &mut portal_view_materials,
+                    PortalViewSettings::new(portal_pairs.color(channel.partner()), pair_shape),
+                );
+            }
+        }
+        None => {
+            insert_unpaired_portal_material(
+                &mut commands,
+                new_portal_faces
+                    .into_iter()
+                    .flat_map(|(_, face, proxies, _)| {
+                        std::iter::once(face).chain(proxies.into_iter().map(|(proxy, _)| proxy))
+                    }),
+                &mut portal_view_materials,
+                PortalViewSettings::unpaired(portal_pairs.color(channel), shape),
+            );
+        }
+    }
+}
 
-            new_portal.insert(portal_view_materials.add(PortalViewMaterial {
-                portal_view: portal_view_image_handle,
-            }));
+pub fn close_portal(
+    close_portal: Trigger<ClosePortal>,
+    mut portal_q: Query<(Entity, &PortalChannel, &PortalShape, &mut Portal, &Children)>,
+    mut portal_cam_q: Query<&mut Camera, With<PortalCamera>>,
+    portal_mesh_q: Query<Entity, Or<(With<PortalFace>, With<PortalProxy>)>>,
+    portal_pairs: Res<PortalPairs>,
+    mut portal_view_materials: ResMut<Assets<PortalViewMaterial>>,
+    mut commands: Commands,
+) {
+    let &ClosePortal { channel } = close_portal.event();
 
-            let mut pair_cam = portal_cam_q
-                .iter_mut()
-                .find_map(|(parent, cam)| (parent.get() == pair_entity).then_some(cam))
-                .unwrap();
+    let Some((entity, _, _, mut portal, _)) =
+        portal_q
+            .iter_mut()
+            .find(|(_, &portal_channel, _, portal, _)| {
+                portal_channel == channel && !portal.is_closing()
+            })
+    else {
+        return;
+    };
 
-            pair_cam.is_active = true;
+    portal.close();
+    let Some(pair) = portal.pair.take() else {
+        return;
+    };
 
-            commands
-                .entity(pair_entity)
-                .remove::<Handle<StandardMaterial>>()
-                .insert(portal_view_materials.add(PortalViewMaterial {
-                    portal_view: pair_cam.target.as_image().unwrap().to_owned(),
-                }));
-        }
-        None => {
-            new_portal.insert(standard_materials.add(StandardMaterial {
-                base_color: P::color(),
-                ..Default::default()
-            }));
-        }
+    let Ok((_, _, &pair_shape, mut pair_portal, pair_children)) = portal_q.get_mut(pair) else {
+        return;
+    };
+    if pair_portal.pair != Some(entity) {
+        return;
+    }
+    pair_portal.pair = None;
+
+    // `activate_portal_cameras` would only catch up in the next `PostUpdate`
+    let mut pair_cams = portal_cam_q.iter_many_mut(pair_children);
+    while let Some(mut camera) = pair_cams.fetch_next() {
+        camera.is_active = false;
+    }
+
+    insert_unpaired_portal_material(
+        &mut commands,
+        portal_mesh_q.iter_many(pair_children),
+        &mut portal_view_materials,
+        PortalViewSettings {
+            openness: pair_portal.openness(),
+            ..PortalViewSettings::unpaired(portal_pairs.color(channel.partner()), pair_shape)
+        },
+    );
+}
+
+fn insert_portal_kind<P: PortalKind>(
+    trigger: Trigger<OnAdd, PortalChannel>,
+    channel_q: Query<&PortalChannel>,
+    mut commands: Commands,
+) {
+    let portal = trigger.entity();
+    if channel_q
+        .get(portal)
+        .is_ok_and(|&channel| channel == P::CHANNEL)
+    {
+        commands.entity(portal).insert(P::default());
+    }
+}
+
+fn new_portal_view_image(size: Extent3d) -> Image {
+    let mut img = Image {
+        texture_descriptor: TextureDescriptor {
+            label: None,
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        ..Default::default()
+    };
+    img.resize(size);
+    img
+}
+
+/// Makes the portal's face show the view of the camera at level 0,
+/// and each of the proxies at level `k` show the view of the camera at level `k + 1`,
+/// all of the same viewer.
+///
+/// The proxies at the deepest level show just the portal's color.
+fn insert_portal_view_materials(
+    commands: &mut Commands,
+    face: Entity,
+    proxies: impl IntoIterator<Item = (Entity, usize)>,
+    portal_views: &[Handle<Image>],
+    portal_view_materials: &mut Assets<PortalViewMaterial>,
+    settings: PortalViewSettings,
+) {
+    let materials = portal_views
+        .iter()
+        .map(|portal_view| {
+            portal_view_materials.add(PortalViewMaterial {
+                portal_view: portal_view.clone(),
+                settings,
+            })
+        })
+        .collect::<Vec<_>>();
+    let fallback = portal_view_materials.add(PortalViewMaterial {
+        portal_view: Handle::default(),
+        settings: PortalViewSettings {
+            view: 0,
+            ..settings
+        },
+    });
+
+    commands.entity(face).insert(materials[0].clone());
+
+    for (proxy, level) in proxies {
+        let material = materials.get(level + 1).unwrap_or(&fallback);
+        commands.entity(proxy).insert(material.clone());
+    }
+}
+
+/// Makes the faces and the proxies of a portal without a pair show just its color,
+/// see [`PortalViewSettings::unpaired`].
+fn insert_unpaired_portal_material(
+    commands: &mut Commands,
+    entities: impl IntoIterator<Item = Entity>,
+    portal_view_materials: &mut Assets<PortalViewMaterial>,
+    settings: PortalViewSettings,
+) {
+    let material = portal_view_materials.add(PortalViewMaterial {
+        portal_view: Handle::default(),
+        settings,
+    });
+    for entity in entities {
+        commands.entity(entity).insert(material.clone());
     }
 }
 
@@ -373,6 +1105,40 @@ pub struct PortalViewMaterial {
     #[texture(0)]
     #[sampler(1)]
     portal_view: Handle<Image>,
+    #[uniform(2)]
+    pub settings: PortalViewSettings,
+}
+
+#[derive(Debug, Clone, Copy, ShaderType)]
+pub struct PortalViewSettings {
+    /// Shown instead of the view, where it has not dissolved in yet.
+    pub color: LinearRgba,
+    /// See [`Portal::openness`].
+    pub openness: f32,
+    /// `1` for [`PortalOutline::Ellipse`], `0` otherwise.
+    pub ellipse: u32,
+    /// `1` when the view dissolves in over the `color`, `0` for just the `color`.
+    pub view: u32,
+}
+
+impl PortalViewSettings {
+    /// The settings of a portal which has not started opening yet.
+    pub fn new(color: Color, shape: PortalShape) -> Self {
+        Self {
+            color: color.into(),
+            openness: 0.,
+            ellipse: (shape.outline == PortalOutline::Ellipse).into(),
+            view: 1,
+        }
+    }
+
+    /// Like [`PortalViewSettings::new`], for a portal without a pair to show the view of.
+    pub fn unpaired(color: Color, shape: PortalShape) -> Self {
+        Self {
+            view: 0,
+            ..Self::new(color, shape)
+        }
+    }
 }
 
 impl Material for PortalViewMaterial {
@@ -385,53 +1151,83 @@ impl Material for PortalViewMaterial {
     }
 }
 
+/// Moves the portal cameras relative to the player's camera.
+///
+/// The portals may be attached to moving surfaces, so their global transforms are computed
+/// from the hierarchy, instead of relying on the ones propagated in the previous frame.
+#[allow(clippy::type_complexity)]
 pub fn move_portal_camera(
-    portal_q: Query<(Entity, &GlobalTransform, &Portal)>,
-    mut portal_cam_q: Query<
-        (&mut Transform, &mut PortalPerspectiveProjection, &Parent),
-        With<PortalCamera>,
-    >,
-    player_cam_q: Query<&GlobalTransform, With<PlayerCamera>>,
+    portal_q: Query<&Portal>,
+    mut portal_cam_q: Query<(
+        Entity,
+        &mut PortalPerspectiveProjection,
+        &PortalCamera,
+        &Parent,
+    )>,
+    player_cam_q: Query<(Entity, &PlayerCamera)>,
+    mut transforms: ParamSet<(TransformHelper, Query<&mut Transform, With<PortalCamera>>)>,
 ) {
-    let player_cam_gt = player_cam_q.single();
-    for (portal, portal_gt, pair) in portal_q
+    let player_cam_gts = player_cam_q
         .iter()
-        .filter_map(|(portal, gt, &Portal { pair })| pair.map(|pair| (portal, gt, pair)))
+        .filter_map(|(player_cam, &PlayerCamera { index })| {
+            let player_cam_gt = transforms.p0().compute_global_transform(player_cam).ok()?;
+            Some((index, player_cam_gt))
+        })
+        .collect::<Vec<_>>();
+
+    let mut new_portal_cam_transforms = Vec::new();
+    for (portal_cam, mut projection, &PortalCamera { viewer, level }, parent) in
+        portal_cam_q.iter_mut()
     {
-        let (mut portal_cam_t, mut projection) = portal_cam_q
-            .iter_mut()
-            .find_map(|(transform, projection, parent)| {
-                (parent.get() == portal).then_some((transform, projection))
-            })
-            .unwrap();
-        let (_, pair_portal_gt, _) = portal_q.get(pair).unwrap();
-
-        let new_portal_cam_gt_mat = pair_portal_gt.compute_matrix()
-            * Mat4::from_rotation_translation(
-                Quat::from_axis_angle(Vec3::Y, std::f32::consts::PI),
-                Vec3::ZERO,
-            )
-            * portal_gt.compute_matrix().inverse()
-            * player_cam_gt.compute_matrix();
-        let (new_scale, new_rotation, new_translation) = (portal_gt.compute_matrix().inverse()
-            * new_portal_cam_gt_mat)
-            .to_scale_rotation_translation();
+        let Some(pair) = portal_q.get(parent.get()).ok().and_then(Portal::pair) else {
+            continue;
+        };
+        let Some((_, player_cam_gt)) = player_cam_gts.iter().find(|&&(index, _)| index == viewer)
+        else {
+            continue;
+        };
+        let transform_helper = transforms.p0();
+        let (Ok(portal_gt), Ok(pair_portal_gt)) = (
+            transform_helper.compute_global_transform(parent.get()),
+            transform_helper.compute_global_transform(pair),
+        ) else {
+            continue;
+        };
 
-        portal_cam_t.scale = new_scale;
-        portal_cam_t.rotation = new_rotation;
-        portal_cam_t.translation = new_translation;
+        // each level looks through the portal once more than the previous one
+        let transfer = portal_transfer_matrix(&portal_gt, &pair_portal_gt);
+        let new_portal_cam_gt_mat =
+            (0..=level).fold(player_cam_gt.compute_matrix(), |mat, _| transfer * mat);
+        new_portal_cam_transforms.push((
+            portal_cam,
+            Transform::from_matrix(portal_gt.compute_matrix().inverse() * new_portal_cam_gt_mat),
+        ));
 
-        // TODO: update near plane so that it is coplanar with the portal's mesh
+        // the geometry in between the camera and the exit portal must not leak into the view,
+        // so the near plane is made coplanar with the exit portal
+        let view_from_world = new_portal_cam_gt_mat.inverse();
+        let clip_normal = view_from_world
+            .transform_vector3(*pair_portal_gt.back())
+            .normalize();
+        let clip_point = view_from_world.transform_point3(pair_portal_gt.translation());
+        projection.clip_plane = Some(clip_normal.extend(-clip_normal.dot(clip_point)));
 
         projection.near = (pair_portal_gt.translation() - new_portal_cam_gt_mat.w_axis.xyz())
             .length()
             .clamp(0.05, projection.far);
     }
+
+    let mut portal_cam_transform_q = transforms.p1();
+    for (portal_cam, new_transform) in new_portal_cam_transforms {
+        if let Ok(mut portal_cam_t) = portal_cam_transform_q.get_mut(portal_cam) {
+            *portal_cam_t = new_transform;
+        }
+    }
 }
 
 pub fn portal_camera_gizmo(
-    portal1_q: Query<&Portal1>,
-    portal2_q: Query<&Portal2>,
+    portal_q: Query<&PortalChannel>,
+    portal_pairs: Res<PortalPairs>,
     portal_cam_q: Query<
         (&GlobalTransform, &PortalPerspectiveProjection, &Parent),
         With<PortalCamera>,
@@ -439,11 +1235,7 @@ pub fn portal_camera_gizmo(
     mut gizmos: Gizmos,
 ) {
     for (gt, projection, portal) in portal_cam_q.iter() {
-        let color = portal1_q
-            .get(portal.get())
-            .map(|_| Portal1::color())
-            .or_else(|_| portal2_q.get(portal.get()).map(|_| Portal2::color()))
-            .unwrap();
+        let color = portal_pairs.color(*portal_q.get(portal.get()).unwrap());
 
         gizmos.cuboid(*gt, color);
         gizmos.arrow(gt.translation(), gt.translation() + *gt.forward(), color);
@@ -457,40 +1249,198 @@ pub fn portal_camera_gizmo(
     }
 }
 
-pub fn resize_portal_view_image(
-    mut resize_events: EventReader<WindowResized>,
-    window_q: Query<&Window, With<PrimaryWindow>>,
-    portal_cam_q: Query<&Camera, With<PortalCamera>>,
+/// Picks the resolution of each portal face's views by the portal's size on its viewer's screen,
+/// and reallocates the views only when the [`PortalViewResolution::bucket`]
+/// or the viewer's viewport changes.
+#[allow(clippy::too_many_arguments)]
+pub fn adapt_portal_view_resolution(
+    policy: Res<PortalViewResolutionPolicy>,
+    player_cam_q: Query<(&PlayerCamera, &Camera, &GlobalTransform)>,
+    portal_q: Query<(&GlobalTransform, &PortalShape, &Children), With<Portal>>,
+    mut portal_face_q: Query<(Entity, &Parent, &PortalFace, &mut PortalViewResolution)>,
+    portal_cam_q: Query<(&PortalCamera, &Camera)>,
+    portal_proxy_q: Query<&PortalProxy>,
+    portal_view_material_q: Query<&Handle<PortalViewMaterial>>,
     mut images: ResMut<Assets<Image>>,
+    mut portal_view_materials: ResMut<Assets<PortalViewMaterial>>,
 ) {
-    for &WindowResized { window, .. } in resize_events.read() {
-        let Ok(window) = window_q.get(window) else {
-            return;
+    for (face, parent, &PortalFace { viewer }, mut resolution) in portal_face_q.iter_mut() {
+        let Some((_, player_cam, player_cam_gt)) = player_cam_q
+            .iter()
+            .find(|(&PlayerCamera { index }, ..)| index == viewer)
+        else {
+            continue;
+        };
+        let (Some(viewport_size), Ok((portal_gt, shape, children))) = (
+            player_cam.physical_viewport_size(),
+            portal_q.get(parent.get()),
+        ) else {
+            continue;
+        };
+
+        let corners = shape.outer_corners().map(|corner| {
+            player_cam.world_to_ndc(player_cam_gt, portal_gt.transform_point(corner.extend(0.)))
+        });
+        let ndc_size = if corners
+            .iter()
+            .all(|corner| corner.is_some_and(|c| c.z > 0.))
+        {
+            let (min, max) = corners.iter().flatten().fold(
+                (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+                |(min, max), corner| (min.min(corner.xy()), max.max(corner.xy())),
+            );
+            let (min, max) = (min.max(Vec2::NEG_ONE), max.min(Vec2::ONE));
+            ((max - min) / 2.).max(Vec2::ZERO).max_element()
+        } else {
+            // the portal is partly behind the camera, so it may be all around it
+            1.
         };
-        for camera in portal_cam_q.iter() {
-            let Some(image) = images.get_mut(camera.target.as_image().unwrap().id()) else {
+        let distance = portal_gt
+            .translation()
+            .distance(player_cam_gt.translation());
+
+        let bucket = policy.bucket(ndc_size, distance);
+        if resolution.bucket == bucket && resolution.viewport_size == viewport_size {
+            continue;
+        }
+        resolution.bucket = bucket;
+        resolution.viewport_size = viewport_size;
+        let size = resolution.extent();
+
+        for (_, camera) in portal_cam_q
+            .iter_many(children)
+            .filter(|(portal_cam, _)| portal_cam.viewer == viewer)
+        {
+            let Some(image) = camera
+                .target
+                .as_image()
+                .and_then(|image| images.get_mut(image))
+            else {
                 continue;
             };
-            image.resize(Extent3d {
-                width: window.physical_width(),
-                height: window.physical_height(),
-                ..Default::default()
-            })
+            image.resize(size);
+        }
+
+        // the materials are refreshed, so that they do not keep sampling the old textures
+        let proxies = children.iter().copied().filter(|&child| {
+            portal_proxy_q
+                .get(child)
+                .is_ok_and(|proxy| proxy.viewer == viewer)
+        });
+        for material in portal_view_material_q.iter_many(std::iter::once(face).chain(proxies)) {
+            // marks the material as changed
+            portal_view_materials.get_mut(material);
+        }
+    }
+}
+
+/// Closes either or both of the portals of each player's gun.
+pub fn remove_portals(gun_q: Query<(&PortalGun, &Controls)>, mut commands: Commands) {
+    for (gun, controls) in gun_q.iter() {
+        let close_primary = controls.remove_portals || controls.close_portal1;
+        let close_secondary = controls.remove_portals || controls.close_portal2;
+        for (end, close) in [
+            (PortalEnd::Primary, close_primary),
+            (PortalEnd::Secondary, close_secondary),
+        ] {
+            if close {
+                commands.trigger(ClosePortal {
+                    channel: PortalChannel::new(gun.pair, end),
+                });
+            }
         }
     }
 }
 
-pub fn remove_portals(
-    controls: Res<Controls>,
+pub fn advance_portal_lifecycle(
+    time: Res<Time>,
+    mut portal_q: Query<(Entity, &mut Portal)>,
     mut commands: Commands,
-    portal_q: Query<Entity, With<Portal>>,
 ) {
-    if !controls.remove_portals {
-        return;
+    let mut closed_walls = Vec::new();
+    for (entity, mut portal) in portal_q.iter_mut() {
+        portal.state_time += time.delta_seconds();
+        match portal.state {
+            PortalState::Opening if portal.state_time >= PORTAL_OPENING_DURATION => {
+                portal.state = PortalState::Open;
+                portal.state_time = 0.;
+            }
+            PortalState::Closing if portal.state_time >= PORTAL_CLOSING_DURATION => {
+                portal.state = PortalState::Closed;
+                portal.state_time = 0.;
+            }
+            PortalState::Closed => {
+                commands.entity(entity).despawn_recursive();
+                closed_walls.extend(portal.wall);
+            }
+            _ => {}
+        }
+    }
+
+    // the walls left without portals no longer need to run the contact hook
+    for wall in closed_walls {
+        let has_portals = portal_q
+            .iter()
+            .any(|(_, portal)| portal.wall == Some(wall) && portal.state != PortalState::Closed);
+        if !has_portals {
+            if let Some(mut wall) = commands.get_entity(wall) {
+                wall.remove::<ActiveHooks>();
+            }
+        }
+    }
+}
+
+/// Scales and dissolves the views of the opening and closing portals, see `portal_view.wgsl`.
+pub fn animate_portal_views(
+    portal_q: Query<(&Portal, &Children)>,
+    portal_view_material_q: Query<&Handle<PortalViewMaterial>>,
+    mut portal_view_materials: ResMut<Assets<PortalViewMaterial>>,
+) {
+    for (portal, children) in portal_q.iter() {
+        let openness = portal.openness();
+        // the faces and the proxies
+        for material in portal_view_material_q.iter_many(children) {
+            // the materials are only touched while animating, so that they are not re-uploaded
+            if portal_view_materials
+                .get(material)
+                .is_some_and(|material| material.settings.openness != openness)
+            {
+                portal_view_materials
+                    .get_mut(material)
+                    .unwrap()
+                    .settings
+                    .openness = openness;
+            }
+        }
     }
+}
 
-    for portal in portal_q.iter() {
-        commands.entity(portal).despawn_recursive();
+/// Renders the views only through the open portals with an open pair,
+/// when their viewer can see them.
+///
+/// The deeper levels are seen through the pair, so they are rendered
+/// when either of the portals is visible.
+pub fn activate_portal_cameras(
+    portal_q: Query<(&Portal, &visibility::PortalVisibility)>,
+    mut portal_cam_q: Query<(&Parent, &PortalCamera, &mut Camera)>,
+) {
+    for (parent, &PortalCamera { viewer, level }, mut camera) in portal_cam_q.iter_mut() {
+        let is_active = portal_q
+            .get(parent.get())
+            .is_ok_and(|(portal, visibility)| {
+                let Some((pair, pair_visibility)) =
+                    portal.pair().and_then(|pair| portal_q.get(pair).ok())
+                else {
+                    return false;
+                };
+                portal.is_open()
+                    && pair.is_open()
+                    && (visibility.is_visible_to(viewer)
+                        || (level > 0 && pair_visibility.is_visible_to(viewer)))
+            });
+        if camera.is_active != is_active {
+            camera.is_active = is_active;
+        }
     }
 }
 
@@ -518,7 +1468,13 @@ pub struct PortalPerspectiveProjection {
     /// Defaults to a value of `0.1`.
     pub near: f32,
 
-    pub portal_frame: (f32, Plane3d),
+    /// The view space plane `(normal, distance)` which replaces the near plane of the frustum.
+    ///
+    /// The points `p` for which `normal · p + distance >= 0` are visible.
+    /// It must be in front of the camera, otherwise the regular near plane is used.
+    ///
+    /// Defaults to [`None`].
+    pub clip_plane: Option<Vec4>,
 
     /// The distance from the camera in world units of the viewing frustum's far plane.
     ///
@@ -530,7 +1486,36 @@ pub struct PortalPerspectiveProjection {
 
 impl CameraProjection for PortalPerspectiveProjection {
     fn get_clip_from_view(&self) -> Mat4 {
-        Mat4::perspective_infinite_reverse_rh(self.fov, self.aspect_ratio, self.near)
+        let mut clip_from_view =
+            Mat4::perspective_infinite_reverse_rh(self.fov, self.aspect_ratio, self.near);
+
+        let Some(clip_plane) = self.clip_plane.filter(|plane| plane.w < 0.) else {
+            return clip_from_view;
+        };
+
+        // Oblique near plane (Eric Lengyel, 2005), adapted to the infinite reverse-Z projection:
+        // the near half-space `w - z >= 0` is replaced with the clip plane,
+        // and the far half-space `z >= 0` gets tilted to pass through the corner of the frustum
+        // at `far`, which is the farthest one in front of the clip plane.
+        let tan_half_fov = (self.fov / 2.).tan();
+        let far_corner = Vec4::new(
+            clip_plane.x.signum() * self.far * tan_half_fov * self.aspect_ratio,
+            clip_plane.y.signum() * self.far * tan_half_fov,
+            -self.far,
+            1.,
+        );
+        let far_corner_distance = clip_plane.dot(far_corner);
+        if far_corner_distance <= 0. {
+            return clip_from_view;
+        }
+
+        let z_row = clip_from_view.row(3) - clip_plane * (self.far / far_corner_distance);
+        clip_from_view.x_axis.z = z_row.x;
+        clip_from_view.y_axis.z = z_row.y;
+        clip_from_view.z_axis.z = z_row.z;
+        clip_from_view.w_axis.z = z_row.w;
+
+        clip_from_view
     }
 
     fn update(&mut self, width: f32, height: f32) {
@@ -550,10 +1535,30 @@ impl CameraProjection for PortalPerspectiveProjection {
         let b = z_far.abs() * tan_half_fov;
         let aspect_ratio = self.aspect_ratio;
 
-        let near_bottom_right = Vec3A::new(a * aspect_ratio, -a, z_near);
-        let near_top_right = Vec3A::new(a * aspect_ratio, a, z_near);
-        let near_top_left = Vec3A::new(-a * aspect_ratio, a, z_near);
-        let near_bottom_left = Vec3A::new(-a * aspect_ratio, -a, z_near);
+        let mut near_bottom_right = Vec3A::new(a * aspect_ratio, -a, z_near);
+        let mut near_top_right = Vec3A::new(a * aspect_ratio, a, z_near);
+        let mut near_top_left = Vec3A::new(-a * aspect_ratio, a, z_near);
+        let mut near_bottom_left = Vec3A::new(-a * aspect_ratio, -a, z_near);
+
+        // push the near corners along their rays onto the clip plane,
+        // so that the cascades do not cover the clipped away geometry
+        if let Some(clip_plane) = self.clip_plane.filter(|plane| plane.w < 0.) {
+            let normal = Vec3A::from(clip_plane.xyz());
+            let max_scale = if z_near != 0. { z_far / z_near } else { 1. };
+            for corner in [
+                &mut near_bottom_right,
+                &mut near_top_right,
+                &mut near_top_left,
+                &mut near_bottom_left,
+            ] {
+                let along_normal = normal.dot(*corner);
+                if along_normal > 0. {
+                    *corner *= (-clip_plane.w / along_normal)
+                        .max(1.)
+                        .min(max_scale.max(1.));
+                }
+            }
+        }
 
         let far_bottom_right = Vec3A::new(b * aspect_ratio, -b, z_far);
         let far_top_right = Vec3A::new(b * aspect_ratio, b, z_far);
@@ -580,53 +1585,93 @@ impl Default for PortalPerspectiveProjection {
             fov: std::f32::consts::FRAC_PI_4,
             aspect_ratio: 1.0,
             near: 0.1,
-            portal_frame: (
-                0.0,
-                Plane3d {
-                    normal: Dir3::Z,
-                    half_size: Vec2::new(0.5, 1.),
-                },
-            ),
+            clip_plane: None,
             far: 1000.0,
         }
     }
 }
 
-pub struct PortalPlugin<P1: PortalKind<Pair = P2>, P2: PortalKind<Pair = P1>> {
-    _p1: std::marker::PhantomData<P1>,
-    _p2: std::marker::PhantomData<P2>,
+pub struct PortalPlugin {
+    /// How many times the portal views are rendered through the portals.
+    ///
+    /// Defaults to [`DEFAULT_PORTAL_RECURSION_DEPTH`].
+    pub recursion_depth: usize,
+}
+
+impl PortalPlugin {
+    pub fn with_recursion_depth(mut self, recursion_depth: usize) -> Self {
+        self.recursion_depth = recursion_depth;
+        self
+    }
 }
 
-impl<P1: PortalKind<Pair = P2>, P2: PortalKind<Pair = P1>> Default for PortalPlugin<P1, P2> {
+impl Default for PortalPlugin {
     fn default() -> Self {
         Self {
-            _p1: Default::default(),
-            _p2: Default::default(),
+            recursion_depth: DEFAULT_PORTAL_RECURSION_DEPTH,
         }
     }
 }
 
-impl<P1: PortalKind<Pair = P2>, P2: PortalKind<Pair = P1>> Plugin for PortalPlugin<P1, P2> {
+impl Plugin for PortalPlugin {
     fn build(&self, app: &mut App) {
         use bevy::render::camera::CameraProjectionPlugin;
 
         app.register_portal_types()
-            .add_event::<SpawnPortal<P1>>()
-            .add_event::<SpawnPortal<P2>>()
+            .insert_resource(PortalRecursionDepth(self.recursion_depth))
+            .init_resource::<PortalPairs>()
+            .init_resource::<PortalViewResolutionPolicy>()
+            .init_resource::<projectile::PortalProjectileAssets>()
+            .add_event::<SpawnPortal>()
+            .add_event::<ClosePortal>()
+            .add_event::<placement::PortalPlacementFailed>()
+            .add_event::<traversal::PortalTraversed>()
             .add_systems(
                 Update,
                 (
-                    (shoot_portal::<P1, P2>, remove_portals).in_set(input::ButtonInputReactions),
+                    (shoot_portal, remove_portals).in_set(input::ButtonInputReactions),
+                    (advance_portal_lifecycle, animate_portal_views).chain(),
                     debug_info::portal_surface_gizmo,
                     debug_info::portal_gizmo,
+                    projectile::move_portal_projectiles.after(shoot_portal),
+                    adapt_portal_view_resolution,
+                    traversal::track_dynamic_bodies,
+                    slicing::prepare_travellers,
+                    slicing::despawn_orphaned_clones,
+                    fizzler::fizzle,
                 ),
             )
-            .observe(spawn_portal::<P1>)
-            .observe(spawn_portal::<P2>);
+            .add_systems(
+                PhysicsSchedule,
+                (
+                    traversal::traverse_portals,
+                    traversal::skip_traversal_interpolation,
+                )
+                    .chain()
+                    .after(PhysicsSet::Writeback)
+                    .before(physics::record_physics_transforms),
+            )
+            .add_systems(
+                PostUpdate,
+                slicing::slice_travellers.before(TransformSystem::TransformPropagate),
+            )
+            .add_systems(
+                PostUpdate,
+                (visibility::check_portal_visibility, activate_portal_cameras)
+                    .chain()
+                    .after(VisibilitySystems::UpdateFrusta),
+            )
+            .observe(spawn_portal)
+            .observe(close_portal)
+            .observe(insert_portal_kind::<Portal1>)
+            .observe(insert_portal_kind::<Portal2>);
 
         if !app.is_plugin_added::<MaterialPlugin<PortalViewMaterial>>() {
             app.add_plugins(MaterialPlugin::<PortalViewMaterial>::default());
         }
+        if !app.is_plugin_added::<MaterialPlugin<slicing::PortalClipMaterial>>() {
+            app.add_plugins(MaterialPlugin::<slicing::PortalClipMaterial>::default());
+        }
         if !app.is_plugin_added::<CameraProjectionPlugin<PortalPerspectiveProjection>>() {
             app.add_plugins(CameraProjectionPlugin::<PortalPerspectiveProjection>::default());
         }
@@ -635,3 +1680,56 @@ impl<P1: PortalKind<Pair = P2>, P2: PortalKind<Pair = P1>> Plugin for PortalPlug
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The depth of the view space `point` in the normalized device coordinates.
+    fn ndc_depth(projection: &PortalPerspectiveProjection, point: Vec3) -> f32 {
+        let clip = projection.get_clip_from_view() * point.extend(1.);
+        clip.z / clip.w
+    }
+
+    /// An oblique projection clipped by a tilted plane in front of the camera,
+    /// along with a point on the plane and the plane's normal.
+    fn oblique_projection() -> (PortalPerspectiveProjection, Vec3, Vec3) {
+        let normal = Vec3::new(0.2, 0.1, -1.).normalize();
+        let on_plane = Vec3::new(0.3, -0.2, -3.);
+        let projection = PortalPerspectiveProjection {
+            clip_plane: Some(normal.extend(-normal.dot(on_plane))),
+            ..Default::default()
+        };
+        (projection, on_plane, normal)
+    }
+
+    #[test]
+    fn clip_plane_maps_to_the_near_plane() {
+        let (projection, on_plane, _) = oblique_projection();
+
+        // reverse-Z, the near plane is at the depth of 1
+        assert!((ndc_depth(&projection, on_plane) - 1.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn points_behind_the_clip_plane_are_clipped() {
+        let (projection, on_plane, normal) = oblique_projection();
+
+        assert!(ndc_depth(&projection, on_plane - normal * 0.5) > 1.);
+        let in_front = ndc_depth(&projection, on_plane + normal * 0.5);
+        assert!((0. ..1.).contains(&in_front));
+    }
+
+    #[test]
+    fn clip_plane_behind_the_camera_is_ignored() {
+        let projection = PortalPerspectiveProjection {
+            clip_plane: Some(Vec4::new(0., 0., -1., 1.)),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            projection.get_clip_from_view(),
+            PortalPerspectiveProjection::default().get_clip_from_view()
+        );
+    }
+}
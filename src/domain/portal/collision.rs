@@ -0,0 +1,67 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_rapier3d::pipeline::{BevyPhysicsHooks, ContactModificationContextView};
+
+use super::{traversal::PortalCrossing, Portal, PortalShape};
+
+/// How deep behind the portal the wall is passable, m
+const PORTAL_HOLE_DEPTH: f32 = 1.;
+/// m
+const PORTAL_HOLE_FRONT_MARGIN: f32 = 0.05;
+
+/// Lets the travellers pass through the walls within the openings of the open portals,
+/// while the rest of the walls stay solid.
+///
+/// The walls get [`ActiveHooks::MODIFY_SOLVER_CONTACTS`](bevy_rapier3d::geometry::ActiveHooks)
+/// when a portal is placed onto them, until their last portal is closed.
+#[derive(SystemParam)]
+pub struct PortalPhysicsHooks<'w, 's> {
+    portal_q: Query<
+        'w,
+        's,
+        (
+            &'static GlobalTransform,
+            &'static PortalShape,
+            &'static Portal,
+        ),
+    >,
+    traveller_q: Query<'w, 's, (), With<PortalCrossing>>,
+}
+
+impl BevyPhysicsHooks for PortalPhysicsHooks<'_, '_> {
+    fn modify_solver_contacts(&self, context: ContactModificationContextView) {
+        let (collider1, collider2) = (context.collider1(), context.collider2());
+        let wall = if self.traveller_q.contains(collider1) {
+            collider2
+        } else if self.traveller_q.contains(collider2) {
+            collider1
+        } else {
+            return;
+        };
+
+        let holes = self
+            .portal_q
+            .iter()
+            .filter(|(.., portal)| {
+                portal.wall() == Some(wall)
+                    && portal.is_open()
+                    && portal
+                        .pair()
+                        .and_then(|pair| self.portal_q.get(pair).ok())
+                        .is_some_and(|(.., pair)| pair.is_open())
+            })
+            .map(|(portal_gt, &shape, _)| (portal_gt.affine().inverse(), shape))
+            .collect::<Vec<_>>();
+        if holes.is_empty() {
+            return;
+        }
+
+        context.raw.solver_contacts.retain(|contact| {
+            let point = Vec3::from(contact.point);
+            !holes.iter().any(|(world_to_portal, shape)| {
+                let point = world_to_portal.transform_point3(point);
+                (-PORTAL_HOLE_DEPTH..=PORTAL_HOLE_FRONT_MARGIN).contains(&point.z)
+                    && shape.contains(point.xy())
+            })
+        });
+    }
+}
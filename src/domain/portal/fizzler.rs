@@ -0,0 +1,85 @@
+use bevy::{ecs::entity::EntityHashSet, prelude::*};
+use bevy_rapier3d::{
+    dynamics::RigidBody,
+    geometry::{ActiveEvents, Collider, CollisionGroups, Group, Sensor},
+    pipeline::CollisionEvent,
+    plugin::RapierContext,
+};
+
+use crate::domain::{player::PLAYER_COLLISION_GROUP, scene::DYNAMIC_COLLISION_GROUP};
+
+use super::{ClosePortal, PortalChannel, PortalEnd, PortalGun};
+
+/// Only the players and the dynamic bodies pass through the fizzlers,
+/// the portal gun's shots do not hit them.
+pub const PORTAL_FIZZLER_COLLISION_GROUP: Group = Group::GROUP_6;
+
+/// An emancipation grid, which closes the portals of the players passing through it,
+/// and destroys the [`Dissolvable`] bodies.
+#[derive(Debug, Default, Component, Reflect)]
+#[reflect(Component, Default)]
+pub struct PortalFizzler;
+
+/// A dynamic body that is despawned when it passes through a [`PortalFizzler`].
+#[derive(Debug, Default, Component, Reflect)]
+#[reflect(Component, Default)]
+pub struct Dissolvable;
+
+/// A [`PortalFizzler`] box sensor, with the `half_extents` in m.
+///
+/// Does not include a transform, so that it can be spawned along with a mesh.
+pub fn portal_fizzler_bundle(half_extents: Vec3) -> impl Bundle {
+    (
+        PortalFizzler,
+        Collider::cuboid(half_extents.x, half_extents.y, half_extents.z),
+        Sensor,
+        ActiveEvents::COLLISION_EVENTS,
+        CollisionGroups::new(
+            PORTAL_FIZZLER_COLLISION_GROUP,
+            PLAYER_COLLISION_GROUP | DYNAMIC_COLLISION_GROUP,
+        ),
+    )
+}
+
+/// Reacts to the bodies entering the fizzlers.
+pub fn fizzle(
+    mut collision_events: EventReader<CollisionEvent>,
+    fizzler_q: Query<(), With<PortalFizzler>>,
+    gun_q: Query<&PortalGun>,
+    dissolvable_q: Query<&RigidBody, With<Dissolvable>>,
+    rapier_ctx: Res<RapierContext>,
+    mut commands: Commands,
+) {
+    let mut dissolved = EntityHashSet::default();
+
+    for &event in collision_events.read() {
+        let CollisionEvent::Started(collider1, collider2, _) = event else {
+            continue;
+        };
+        let collider = if fizzler_q.contains(collider1) {
+            collider2
+        } else if fizzler_q.contains(collider2) {
+            collider1
+        } else {
+            continue;
+        };
+        // the collider may be a child of the body
+        let body = rapier_ctx.collider_parent(collider).unwrap_or(collider);
+
+        if let Ok(gun) = gun_q.get(body) {
+            for end in [PortalEnd::Primary, PortalEnd::Secondary] {
+                commands.trigger(ClosePortal {
+                    channel: PortalChannel::new(gun.pair, end),
+                });
+            }
+        }
+
+        if dissolvable_q
+            .get(body)
+            .is_ok_and(|body| matches!(body, RigidBody::Dynamic))
+            && dissolved.insert(body)
+        {
+            commands.entity(body).despawn_recursive();
+        }
+    }
+}
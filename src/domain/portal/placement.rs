@@ -0,0 +1,242 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_rapier3d::{
+    geometry::{Collider, CollisionGroups},
+    pipeline::QueryFilter,
+    plugin::RapierContext,
+};
+
+use crate::domain::scene::STATIC_COLLISION_GROUP;
+
+use super::{
+    raycast::PortalRayHit, PortalChannel, PortalShape, PortalState, PortalSurface, SpawnPortal,
+    PORTAL_RAY_COLLISION_GROUP,
+};
+
+/// m
+const PORTAL_SURFACE_OFFSET: f32 = 0.01;
+/// The distance from the surface within which the other portals are considered coplanar, m
+const OVERLAP_DEPTH: f32 = 0.05;
+/// The depth of the volume in front of the portal that must be free of the static geometry, m
+const OBSTRUCTION_DEPTH: f32 = 0.1;
+/// Lets the portal touch the geometry at its edges, m
+const OBSTRUCTION_MARGIN: f32 = 0.01;
+/// The surfaces with the normal's |Y| at least this are treated as floors or ceilings
+const HORIZONTAL_SURFACE_MIN_NORMAL_Y: f32 = 0.9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortalPlacementFailure {
+    /// The shot did not hit anything.
+    Missed,
+    /// The shot hit something which is not a [`PortalSurface`].
+    NotPortalable(Entity),
+    /// The hit point is outside of the [`PortalSurface`].
+    OutsideSurface,
+    /// The [`PortalSurface`] cannot fit the portal's [`PortalShape`].
+    SurfaceTooSmall,
+    /// The portal would overlap another portal.
+    OverlapsPortal(Entity),
+    /// The static geometry is in the way of the portal.
+    Obstructed(Entity),
+}
+
+#[derive(Debug, Clone, Copy, Event)]
+pub struct PortalPlacementFailed {
+    pub channel: PortalChannel,
+    pub reason: PortalPlacementFailure,
+}
+
+/// Places the portals where the shots of the portal gun hit,
+/// or reports [`PortalPlacementFailed`].
+#[derive(SystemParam)]
+pub struct PortalPlacer<'w, 's> {
+    portal_surface_q: Query<
+        'w,
+        's,
+        (
+            &'static GlobalTransform,
+            &'static PortalSurface,
+            Option<&'static Parent>,
+        ),
+    >,
+    portal_q: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static GlobalTransform,
+            &'static PortalShape,
+            &'static PortalChannel,
+        ),
+    >,
+    rapier_ctx: Res<'w, RapierContext>,
+    commands: Commands<'w, 's>,
+    placement_failed: EventWriter<'w, PortalPlacementFailed>,
+}
+
+impl PortalPlacer<'_, '_> {
+    /// Spawns the portal at the `channel` if the shot travelling along the `ray_dir`
+    /// has hit a [`PortalSurface`] which fits it.
+    pub fn place(
+        &mut self,
+        channel: PortalChannel,
+        shape: PortalShape,
+        hit: Option<PortalRayHit>,
+        ray_dir: Vec3,
+    ) {
+        let Some(PortalRayHit { entity, point, .. }) = hit else {
+            self.fail(channel, PortalPlacementFailure::Missed);
+            return;
+        };
+
+        let Ok((transform, surface, surface_parent)) = self.portal_surface_q.get(entity) else {
+            self.fail(channel, PortalPlacementFailure::NotPortalable(entity));
+            return;
+        };
+
+        let portal_transform = match place_portal(
+            transform,
+            surface,
+            shape,
+            point,
+            ray_dir,
+            self.portal_q
+                .iter()
+                .filter(|&(.., &portal_channel)| portal_channel != channel)
+                .map(|(portal, portal_gt, portal_shape, _)| (portal, portal_gt, portal_shape)),
+            &self.rapier_ctx,
+        ) {
+            Ok(portal_transform) => portal_transform,
+            Err(reason) => {
+                self.fail(channel, reason);
+                return;
+            }
+        };
+
+        self.commands.trigger(SpawnPortal {
+            channel,
+            transform: portal_transform,
+            shape,
+            state: PortalState::Opening,
+            surface: Some(entity),
+            // the surface itself is a sensor, the solid collider is its parent
+            wall: surface_parent.map(Parent::get),
+        });
+    }
+
+    fn fail(&mut self, channel: PortalChannel, reason: PortalPlacementFailure) {
+        self.placement_failed
+            .send(PortalPlacementFailed { channel, reason });
+    }
+}
+
+/// Finds the transform of a portal placed onto the surface at the `point`,
+/// shifting it to fit the surface if needed.
+///
+/// On floors and ceilings the portal's up is turned towards the `view_forward`,
+/// elsewhere the portal keeps the surface's rotation.
+///
+/// The `portals` are the other portals that may not be overlapped,
+/// so the portal being replaced should not be among them.
+pub fn place_portal<'a>(
+    surface_gt: &GlobalTransform,
+    &PortalSurface { size }: &PortalSurface,
+    shape: PortalShape,
+    point: Vec3,
+    view_forward: Vec3,
+    portals: impl IntoIterator<Item = (Entity, &'a GlobalTransform, &'a PortalShape)>,
+    rapier_ctx: &RapierContext,
+) -> Result<Transform, PortalPlacementFailure> {
+    let point_on_plane = surface_gt.affine().inverse().transform_point3(point).xy();
+
+    let half_size = size * surface_gt.to_scale_rotation_translation().0.xy() / 2.;
+
+    if point_on_plane.abs().cmpgt(half_size).any() {
+        return Err(PortalPlacementFailure::OutsideSurface);
+    }
+
+    let mut portal_transform = surface_gt.compute_transform();
+
+    let roll = portal_roll(portal_transform.rotation, view_forward);
+    portal_transform.rotation *= Quat::from_rotation_z(roll);
+
+    // the bounds of the rolled portal on the surface
+    let (sin, cos) = roll.sin_cos();
+    let portal_half_size = Vec2::new(
+        cos.abs() * shape.size.x + sin.abs() * shape.size.y,
+        sin.abs() * shape.size.x + cos.abs() * shape.size.y,
+    ) / 2.;
+
+    if half_size.cmplt(portal_half_size).any() {
+        return Err(PortalPlacementFailure::SurfaceTooSmall);
+    }
+
+    let clamped_point = surface_gt.affine().transform_point3(
+        point_on_plane
+            .clamp(-half_size + portal_half_size, half_size - portal_half_size)
+            .extend(0.),
+    );
+    portal_transform.translation +=
+        (clamped_point - surface_gt.translation()) + surface_gt.back() * PORTAL_SURFACE_OFFSET;
+
+    let world_to_portal = portal_transform.compute_affine().inverse();
+    for (portal, portal_gt, other_shape) in portals {
+        let other = world_to_portal.transform_point3(portal_gt.translation());
+        // the bounds of the other portal, as seen in this portal's frame
+        let other_half_size = world_to_portal
+            .transform_vector3(*portal_gt.right())
+            .xy()
+            .abs()
+            * other_shape.half_size().x
+            + world_to_portal
+                .transform_vector3(*portal_gt.up())
+                .xy()
+                .abs()
+                * other_shape.half_size().y;
+        if other.z.abs() < OVERLAP_DEPTH
+            && other
+                .xy()
+                .abs()
+                .cmplt(shape.half_size() + other_half_size)
+                .all()
+        {
+            return Err(PortalPlacementFailure::OverlapsPortal(portal));
+        }
+    }
+
+    if let Some(obstacle) = rapier_ctx.intersection_with_shape(
+        portal_transform.translation
+            + portal_transform.back() * (OBSTRUCTION_MARGIN + OBSTRUCTION_DEPTH / 2.),
+        portal_transform.rotation,
+        &Collider::cuboid(
+            shape.half_size().x - OBSTRUCTION_MARGIN,
+            shape.half_size().y - OBSTRUCTION_MARGIN,
+            OBSTRUCTION_DEPTH / 2.,
+        ),
+        QueryFilter::new()
+            .exclude_sensors()
+            .groups(CollisionGroups::new(
+                PORTAL_RAY_COLLISION_GROUP,
+                STATIC_COLLISION_GROUP,
+            )),
+    ) {
+        return Err(PortalPlacementFailure::Obstructed(obstacle));
+    }
+
+    Ok(portal_transform)
+}
+
+/// The angle around the surface normal, by which the portal's up is turned
+/// towards the `view_forward` projected onto the surface, if it is a floor or a ceiling.
+fn portal_roll(surface_rotation: Quat, view_forward: Vec3) -> f32 {
+    let normal = surface_rotation * Vec3::Z;
+    if normal.y.abs() < HORIZONTAL_SURFACE_MIN_NORMAL_Y {
+        return 0.;
+    }
+
+    let forward_on_surface = (surface_rotation.inverse() * view_forward).xy();
+    if forward_on_surface.length_squared() <= f32::EPSILON {
+        return 0.;
+    }
+
+    Vec2::Y.angle_between(forward_on_surface)
+}
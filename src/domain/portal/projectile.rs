@@ -0,0 +1,126 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use super::{
+    placement::PortalPlacer, portal_shot_filter, raycast::PortalRaycast, PortalChannel,
+    PortalShape, PORTAL_GUN_MAX_HOPS,
+};
+
+/// m
+const PORTAL_PROJECTILE_RADIUS: f32 = 0.05;
+
+/// A shot of the portal gun in [`PortalGunMode::Projectile`](super::PortalGunMode::Projectile),
+/// which places the portal at the `channel` where it hits.
+#[derive(Debug, Clone, Copy, Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct PortalProjectile {
+    pub channel: PortalChannel,
+    /// The shape of the portal placed by the projectile.
+    pub shape: PortalShape,
+    /// m/s
+    pub velocity: Vec3,
+    /// The distance the projectile flies before it fizzles out, m
+    pub range_left: f32,
+}
+
+/// The mesh and the materials shared by all the projectiles.
+#[derive(Debug, Resource)]
+pub struct PortalProjectileAssets {
+    mesh: Handle<Mesh>,
+    materials: HashMap<PortalChannel, Handle<StandardMaterial>>,
+}
+
+impl FromWorld for PortalProjectileAssets {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            mesh: world
+                .resource_mut::<Assets<Mesh>>()
+                .add(Sphere::new(PORTAL_PROJECTILE_RADIUS)),
+            materials: HashMap::default(),
+        }
+    }
+}
+
+impl PortalProjectileAssets {
+    /// The material of the projectiles of the `channel`, created on its first shot.
+    fn material(
+        &mut self,
+        channel: PortalChannel,
+        color: Color,
+        standard_materials: &mut Assets<StandardMaterial>,
+    ) -> Handle<StandardMaterial> {
+        self.materials
+            .entry(channel)
+            .or_insert_with(|| {
+                standard_materials.add(StandardMaterial {
+                    base_color: color,
+                    emissive: color.into(),
+                    unlit: true,
+                    ..Default::default()
+                })
+            })
+            .clone()
+    }
+}
+
+pub fn portal_projectile_bundle(
+    projectile: PortalProjectile,
+    translation: Vec3,
+    color: Color,
+    projectile_assets: &mut PortalProjectileAssets,
+    standard_materials: &mut Assets<StandardMaterial>,
+) -> impl Bundle {
+    (
+        Name::new("Portal Projectile"),
+        projectile,
+        PbrBundle {
+            mesh: projectile_assets.mesh.clone(),
+            material: projectile_assets.material(projectile.channel, color, standard_materials),
+            transform: Transform::from_translation(translation),
+            ..Default::default()
+        },
+    )
+}
+
+/// Moves the projectiles through the portals in their way,
+/// and places the portals where they hit.
+pub fn move_portal_projectiles(
+    time: Res<Time>,
+    mut projectile_q: Query<(Entity, &mut Transform, &mut PortalProjectile)>,
+    portal_raycast: PortalRaycast,
+    mut portal_placer: PortalPlacer,
+    mut commands: Commands,
+) {
+    for (entity, mut transform, mut projectile) in projectile_q.iter_mut() {
+        let speed = projectile.velocity.length();
+        let step = (speed * time.delta_seconds()).min(projectile.range_left);
+        if step == 0. {
+            continue;
+        }
+
+        let path = portal_raycast.cast_ray(
+            transform.translation,
+            projectile.velocity / speed,
+            step,
+            true,
+            portal_shot_filter(),
+            PORTAL_GUN_MAX_HOPS,
+        );
+        let (Some(segment), Some(direction)) = (path.segments.last(), path.final_direction())
+        else {
+            // the ray is degenerate, so the shot is reported as missed
+            portal_placer.place(projectile.channel, projectile.shape, None, Vec3::ZERO);
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+
+        projectile.range_left -= step;
+        if path.hit.is_some() || projectile.range_left <= 0. {
+            portal_placer.place(projectile.channel, projectile.shape, path.hit, direction);
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        transform.translation = segment.end;
+        projectile.velocity = direction * speed;
+    }
+}
@@ -0,0 +1,152 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_rapier3d::{pipeline::QueryFilter, plugin::RapierContext};
+
+use super::{portal_transfer_matrix, Portal, PortalShape};
+
+/// A straight part of a ray cast by [`PortalRaycast`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PortalRaySegment {
+    pub start: Vec3,
+    pub end: Vec3,
+    /// The portal the ray enters at the `end`, if it continues out of its pair.
+    pub portal: Option<Entity>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PortalRayHit {
+    pub entity: Entity,
+    pub point: Vec3,
+    /// The time of impact summed over all the segments, in the units of the ray's direction.
+    pub time_of_impact: f32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PortalRayPath {
+    /// At least one segment, unless the ray has a zero length.
+    pub segments: Vec<PortalRaySegment>,
+    pub hit: Option<PortalRayHit>,
+}
+
+impl PortalRayPath {
+    /// The direction of the ray after it has passed through all the portals.
+    pub fn final_direction(&self) -> Option<Vec3> {
+        self.segments
+            .last()
+            .map(|segment| (segment.end - segment.start).normalize_or_zero())
+    }
+}
+
+/// Casts rays which enter the open portals and continue out of their pairs.
+#[derive(SystemParam)]
+pub struct PortalRaycast<'w, 's> {
+    rapier_ctx: Res<'w, RapierContext>,
+    portal_q: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static GlobalTransform,
+            &'static PortalShape,
+            &'static Portal,
+        ),
+    >,
+}
+
+impl PortalRaycast<'_, '_> {
+    /// Like [`RapierContext::cast_ray`], but passes through at most `max_hops` portals.
+    ///
+    /// The `max_toi` is the total over all the segments.
+    pub fn cast_ray(
+        &self,
+        ray_origin: Vec3,
+        ray_dir: Vec3,
+        max_toi: f32,
+        solid: bool,
+        filter: QueryFilter,
+        max_hops: usize,
+    ) -> PortalRayPath {
+        let mut path = PortalRayPath::default();
+        let (mut origin, mut dir) = (ray_origin, ray_dir);
+        let mut toi_left = max_toi;
+        let mut hops = 0;
+
+        while toi_left > 0. {
+            let hit = self
+                .rapier_ctx
+                .cast_ray(origin, dir, toi_left, solid, filter);
+            let hit_toi = hit.map_or(toi_left, |(_, toi)| toi);
+
+            let portal = (hops < max_hops)
+                .then(|| self.first_portal(origin, dir, hit_toi))
+                .flatten();
+
+            let Some((portal, portal_toi, transfer)) = portal else {
+                let end = origin + dir * hit_toi;
+                path.segments.push(PortalRaySegment {
+                    start: origin,
+                    end,
+                    portal: None,
+                });
+                path.hit = hit.map(|(entity, toi)| PortalRayHit {
+                    entity,
+                    point: end,
+                    time_of_impact: max_toi - toi_left + toi,
+                });
+                break;
+            };
+
+            let end = origin + dir * portal_toi;
+            path.segments.push(PortalRaySegment {
+                start: origin,
+                end,
+                portal: Some(portal),
+            });
+
+            origin = transfer.transform_point3(end);
+            dir = transfer.transform_vector3(dir);
+            toi_left -= portal_toi;
+            hops += 1;
+        }
+
+        path
+    }
+
+    /// The nearest open portal entered by the ray before `max_toi`,
+    /// along with the time of impact and the transfer matrix to its pair.
+    fn first_portal(&self, origin: Vec3, dir: Vec3, max_toi: f32) -> Option<(Entity, f32, Mat4)> {
+        self.portal_q
+            .iter()
+            .filter(|(.., portal)| portal.is_open())
+            .filter_map(|(entity, portal_gt, &shape, portal)| {
+                let (_, pair_gt, _, pair) = self.portal_q.get(portal.pair()?).ok()?;
+                if !pair.is_open() {
+                    return None;
+                }
+                let toi =
+                    ray_portal_toi(portal_gt, shape, origin, dir).filter(|&toi| toi <= max_toi)?;
+                Some((entity, toi, portal_transfer_matrix(portal_gt, pair_gt)))
+            })
+            .min_by(|(_, a, _), (_, b, _)| a.total_cmp(b))
+    }
+}
+
+/// The time of impact of the ray entering the portal's opening from its front.
+fn ray_portal_toi(
+    portal_gt: &GlobalTransform,
+    shape: PortalShape,
+    origin: Vec3,
+    dir: Vec3,
+) -> Option<f32> {
+    let world_to_portal = portal_gt.affine().inverse();
+    let origin = world_to_portal.transform_point3(origin);
+    let dir = world_to_portal.transform_vector3(dir);
+
+    if origin.z < 0. || dir.z >= 0. {
+        return None;
+    }
+
+    let toi = -origin.z / dir.z;
+    let point_on_plane = (origin + dir * toi).xy();
+
+    shape.contains(point_on_plane).then_some(toi)
+}
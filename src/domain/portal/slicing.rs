@@ -0,0 +1,200 @@
+use bevy::{
+    pbr::{ExtendedMaterial, MaterialExtension},
+    prelude::*,
+    render::{
+        primitives::Aabb,
+        render_resource::{AsBindGroup, ShaderRef},
+        view::RenderLayers,
+    },
+};
+
+use super::{portal_transfer_matrix, Portal, PortalShape};
+
+/// Keeps every fragment, see [`PortalClipExtension::clip_plane`].
+const NO_CLIP_PLANE: Vec4 = Vec4::W;
+
+pub type PortalClipMaterial = ExtendedMaterial<StandardMaterial, PortalClipExtension>;
+
+#[derive(Debug, Clone, Asset, AsBindGroup, Reflect)]
+pub struct PortalClipExtension {
+    /// The world space plane `(normal, distance)`.
+    ///
+    /// The fragments at `p` for which `normal · p + distance < 0` are discarded.
+    #[uniform(100)]
+    pub clip_plane: Vec4,
+}
+
+impl MaterialExtension for PortalClipExtension {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/portal_clip.wgsl".into()
+    }
+
+    fn deferred_fragment_shader() -> ShaderRef {
+        "shaders/portal_clip.wgsl".into()
+    }
+}
+
+/// Renders the entity cut by the portal it is halfway through,
+/// with the other part of it coming out of the paired portal.
+///
+/// The entity's [`StandardMaterial`] gets replaced with a [`PortalClipMaterial`].
+#[derive(Debug, Default, Component, Reflect)]
+#[reflect(Component, Default)]
+pub struct PortalTraveller;
+
+/// The part of the `traveller` that comes out of the exit portal.
+#[derive(Debug, Component, Reflect)]
+#[reflect(Component)]
+pub struct PortalCloneOf {
+    pub traveller: Entity,
+}
+
+/// The link from the traveller to its [`PortalCloneOf`], while it is halfway through a portal.
+#[derive(Debug, Component, Reflect)]
+#[reflect(Component)]
+pub struct TravellerClone(Entity);
+
+pub fn prepare_travellers(
+    traveller_q: Query<(Entity, &Handle<StandardMaterial>), With<PortalTraveller>>,
+    standard_materials: Res<Assets<StandardMaterial>>,
+    mut clip_materials: ResMut<Assets<PortalClipMaterial>>,
+    mut commands: Commands,
+) {
+    for (traveller, material) in traveller_q.iter() {
+        let Some(base) = standard_materials.get(material) else {
+            continue;
+        };
+        commands
+            .entity(traveller)
+            .remove::<Handle<StandardMaterial>>()
+            .insert(clip_materials.add(PortalClipMaterial {
+                base: base.clone(),
+                extension: PortalClipExtension {
+                    clip_plane: NO_CLIP_PLANE,
+                },
+            }));
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn slice_travellers(
+    portal_q: Query<(&GlobalTransform, &PortalShape, &Portal)>,
+    traveller_q: Query<
+        (
+            Entity,
+            &Transform,
+            &Aabb,
+            &Handle<Mesh>,
+            &Handle<PortalClipMaterial>,
+            Option<&RenderLayers>,
+            Option<&TravellerClone>,
+        ),
+        (With<PortalTraveller>, Without<PortalCloneOf>),
+    >,
+    mut clone_q: Query<(&mut Transform, &Handle<PortalClipMaterial>), With<PortalCloneOf>>,
+    mut clip_materials: ResMut<Assets<PortalClipMaterial>>,
+    mut commands: Commands,
+) {
+    for (traveller, transform, aabb, mesh, material, render_layers, clone) in traveller_q.iter() {
+        let center = transform.transform_point(aabb.center.into());
+        let radius = Vec3::from(aabb.half_extents).length() * transform.scale.max_element();
+
+        let halfway = portal_q.iter().find_map(|(entry_gt, shape, portal)| {
+            let (exit_gt, _, exit_portal) = portal_q.get(portal.pair()?).ok()?;
+            if !portal.is_open() || !exit_portal.is_open() {
+                return None;
+            }
+            let local_center = entry_gt.affine().inverse().transform_point3(center);
+            let is_halfway =
+                local_center.z.abs() < radius && shape.grown(radius).contains(local_center.xy());
+            is_halfway.then_some((entry_gt, exit_gt))
+        });
+
+        let Some((entry_gt, exit_gt)) = halfway else {
+            set_clip_plane(&mut clip_materials, material, NO_CLIP_PLANE);
+            if let Some(&TravellerClone(clone)) = clone {
+                commands.entity(clone).despawn_recursive();
+                commands.entity(traveller).remove::<TravellerClone>();
+            }
+            continue;
+        };
+
+        set_clip_plane(&mut clip_materials, material, portal_front_plane(entry_gt));
+
+        let clone_transform = Transform::from_matrix(
+            portal_transfer_matrix(entry_gt, exit_gt) * transform.compute_matrix(),
+        );
+        let clone_clip_plane = portal_front_plane(exit_gt);
+
+        match clone.and_then(|&TravellerClone(clone)| clone_q.get_mut(clone).ok()) {
+            Some((mut transform, clone_material)) => {
+                *transform = clone_transform;
+                set_clip_plane(&mut clip_materials, clone_material, clone_clip_plane);
+            }
+            None => {
+                let Some(base) = clip_materials
+                    .get(material)
+                    .map(|material| material.base.clone())
+                else {
+                    continue;
+                };
+                let mut new_clone = commands.spawn((
+                    Name::new("Portal Traveller Clone"),
+                    PortalCloneOf { traveller },
+                    MaterialMeshBundle {
+                        mesh: mesh.clone(),
+                        material: clip_materials.add(PortalClipMaterial {
+                            base,
+                            extension: PortalClipExtension {
+                                clip_plane: clone_clip_plane,
+                            },
+                        }),
+                        transform: clone_transform,
+                        ..Default::default()
+                    },
+                ));
+                if let Some(render_layers) = render_layers {
+                    new_clone.insert(render_layers.clone());
+                }
+                let new_clone = new_clone.id();
+                commands.entity(traveller).insert(TravellerClone(new_clone));
+            }
+        }
+    }
+}
+
+pub fn despawn_orphaned_clones(
+    clone_q: Query<(Entity, &PortalCloneOf)>,
+    traveller_q: Query<(), With<PortalTraveller>>,
+    mut commands: Commands,
+) {
+    for (clone, &PortalCloneOf { traveller }) in clone_q.iter() {
+        if !traveller_q.contains(traveller) {
+            commands.entity(clone).despawn_recursive();
+        }
+    }
+}
+
+/// Updates the material only if the plane has changed, so that it is not re-uploaded every frame.
+fn set_clip_plane(
+    clip_materials: &mut Assets<PortalClipMaterial>,
+    material: &Handle<PortalClipMaterial>,
+    clip_plane: Vec4,
+) {
+    if clip_materials
+        .get(material)
+        .is_some_and(|material| material.extension.clip_plane != clip_plane)
+    {
+        clip_materials
+            .get_mut(material)
+            .unwrap()
+            .extension
+            .clip_plane = clip_plane;
+    }
+}
+
+/// The plane of the portal, with the normal pointing out of its front.
+fn portal_front_plane(portal_gt: &GlobalTransform) -> Vec4 {
+    let normal = *portal_gt.back();
+    normal.extend(-normal.dot(portal_gt.translation()))
+}
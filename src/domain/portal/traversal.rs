@@ -0,0 +1,128 @@
+use bevy::prelude::*;
+use bevy_rapier3d::dynamics::{RigidBody, Velocity};
+
+use crate::{domain::player::Player, physics::PhysicsInterpolation};
+
+use super::{portal_transfer_matrix, Portal, PortalShape};
+
+/// Remembers where the traveller was on the previous physics tick,
+/// so that crossing a portal plane in between two ticks can be detected.
+///
+/// Inserted automatically into every [`RigidBody::Dynamic`].
+/// The crossing is tracked by the body's origin, so the collider shape does not matter.
+#[derive(Debug, Default, Component, Reflect)]
+#[reflect(Component, Default)]
+pub struct PortalCrossing {
+    previous_translation: Option<Vec3>,
+}
+
+#[derive(Debug, Clone, Copy, Event)]
+pub struct PortalTraversed {
+    pub traveller: Entity,
+    pub entry: Entity,
+    pub exit: Entity,
+}
+
+pub fn track_dynamic_bodies(
+    body_q: Query<(Entity, &RigidBody), (Added<RigidBody>, Without<PortalCrossing>)>,
+    mut commands: Commands,
+) {
+    for (entity, body) in body_q.iter() {
+        if let RigidBody::Dynamic = body {
+            commands.entity(entity).insert(PortalCrossing::default());
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn traverse_portals(
+    portal_q: Query<(Entity, &GlobalTransform, &PortalShape, &Portal)>,
+    mut traveller_q: Query<(
+        Entity,
+        &mut Transform,
+        &mut PortalCrossing,
+        Option<&mut Velocity>,
+        Has<Player>,
+    )>,
+    mut traversed: EventWriter<PortalTraversed>,
+) {
+    for (traveller, mut transform, mut crossing, velocity, is_player) in traveller_q.iter_mut() {
+        let current = transform.translation;
+        let Some(previous) = crossing.previous_translation.replace(current) else {
+            continue;
+        };
+
+        let Some((entry, exit, transfer)) =
+            portal_q
+                .iter()
+                .find_map(|(entry, entry_gt, &shape, portal)| {
+                    let exit = portal.pair()?;
+                    if !portal.is_open() || !crosses_portal(entry_gt, shape, previous, current) {
+                        return None;
+                    }
+                    let (_, exit_gt, _, exit_portal) = portal_q.get(exit).ok()?;
+                    if !exit_portal.is_open() {
+                        return None;
+                    }
+                    Some((entry, exit, portal_transfer_matrix(entry_gt, exit_gt)))
+                })
+        else {
+            continue;
+        };
+
+        let (_, rotation, translation) =
+            (transfer * transform.compute_matrix()).to_scale_rotation_translation();
+
+        transform.translation = translation;
+        transform.rotation = if is_player {
+            // only the yaw is kept, so that the player stays upright
+            Quat::from_rotation_y(rotation.to_euler(EulerRot::YXZ).0)
+        } else {
+            rotation
+        };
+        crossing.previous_translation = Some(translation);
+
+        if let Some(mut velocity) = velocity {
+            // the momentum is preserved relative to the portals
+            let (_, frame_rotation, _) = transfer.to_scale_rotation_translation();
+            velocity.linvel = frame_rotation * velocity.linvel;
+            velocity.angvel = frame_rotation * velocity.angvel;
+        }
+
+        traversed.send(PortalTraversed {
+            traveller,
+            entry,
+            exit,
+        });
+    }
+}
+
+/// The travellers are not interpolated from where they have entered the portal,
+/// all the way across the level.
+pub fn skip_traversal_interpolation(
+    mut traversed: EventReader<PortalTraversed>,
+    mut interpolation_q: Query<&mut PhysicsInterpolation>,
+) {
+    for &PortalTraversed { traveller, .. } in traversed.read() {
+        if let Ok(mut interpolation) = interpolation_q.get_mut(traveller) {
+            interpolation.teleport();
+        }
+    }
+}
+
+/// Whether the segment `from -> to` goes from the front of the portal to its back
+/// through the portal's opening.
+fn crosses_portal(portal_gt: &GlobalTransform, shape: PortalShape, from: Vec3, to: Vec3) -> bool {
+    let world_to_portal = portal_gt.affine().inverse();
+    let from = world_to_portal.transform_point3(from);
+    let to = world_to_portal.transform_point3(to);
+
+    if from.z < 0. || to.z >= 0. {
+        return false;
+    }
+
+    let t = from.z / (from.z - to.z);
+    let point_on_plane = from.lerp(to, t).xy();
+
+    shape.contains(point_on_plane)
+}
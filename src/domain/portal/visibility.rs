@@ -0,0 +1,78 @@
+use bevy::{
+    prelude::*,
+    render::primitives::{Aabb, Frustum},
+};
+use bevy_rapier3d::plugin::RapierContext;
+
+use crate::domain::player::PlayerCamera;
+
+use super::{portal_shot_filter, PortalShape};
+
+/// The fraction of the portal's inner corners at which they are tested for occlusion,
+/// so that the rays do not graze the surrounding geometry.
+const OCCLUSION_SAMPLE_INSET: f32 = 0.9;
+/// m
+const OCCLUSION_MARGIN: f32 = 0.05;
+
+/// Which of the player cameras can see the portal.
+///
+/// The cameras of the portals hidden from their viewer are not rendered, see
+/// [`activate_portal_cameras`](super::activate_portal_cameras).
+#[derive(Debug, Default, Component, Reflect)]
+#[reflect(Component)]
+pub struct PortalVisibility {
+    /// A bit per [`PlayerCamera::index`].
+    visible_to: u32,
+}
+
+impl PortalVisibility {
+    pub fn is_visible_to(&self, viewer: usize) -> bool {
+        self.visible_to & (1 << viewer) != 0
+    }
+}
+
+/// A portal is hidden from a player camera when it is outside of the camera's frustum,
+/// faces away from it, or all of its center and corners are occluded.
+pub fn check_portal_visibility(
+    player_cam_q: Query<(&PlayerCamera, &GlobalTransform, &Frustum)>,
+    mut portal_q: Query<(&GlobalTransform, &PortalShape, &Aabb, &mut PortalVisibility)>,
+    rapier_ctx: Res<RapierContext>,
+) {
+    for (portal_gt, shape, aabb, mut visibility) in portal_q.iter_mut() {
+        let world_to_portal = portal_gt.affine().inverse();
+
+        let visible_to = player_cam_q
+            .iter()
+            .filter(|(_, player_cam_gt, frustum)| {
+                let eye = player_cam_gt.translation();
+                let faces_viewer = world_to_portal.transform_point3(eye).z > 0.;
+
+                faces_viewer
+                    && frustum.intersects_obb(aabb, &portal_gt.affine(), true, true)
+                    && std::iter::once(Vec2::ZERO)
+                        .chain(shape.inner_corners())
+                        .any(|corner| {
+                            let sample = corner * OCCLUSION_SAMPLE_INSET;
+                            let to_sample = portal_gt.transform_point(sample.extend(0.)) - eye;
+                            let distance = to_sample.length() - OCCLUSION_MARGIN;
+                            distance <= 0.
+                                || rapier_ctx
+                                    .cast_ray(
+                                        eye,
+                                        to_sample.normalize(),
+                                        distance,
+                                        true,
+                                        portal_shot_filter().exclude_sensors(),
+                                    )
+                                    .is_none()
+                        })
+            })
+            .fold(0, |visible_to, (&PlayerCamera { index }, ..)| {
+                visible_to | (1 << index)
+            });
+
+        if visibility.visible_to != visible_to {
+            visibility.visible_to = visible_to;
+        }
+    }
+}
@@ -1,7 +1,14 @@
 use bevy::{prelude::*, render::view::Layer};
 use bevy_rapier3d::prelude::*;
 
-use super::portal::{PortalSurface, PORTAL_RAY_COLLISION_GROUP};
+use super::{
+    level,
+    portal::{
+        fizzler::{portal_fizzler_bundle, Dissolvable},
+        slicing::PortalTraveller,
+        PortalSurface, PORTAL_RAY_COLLISION_GROUP,
+    },
+};
 
 pub const GROUND_RENDER_LAYER: Layer = 0;
 
@@ -9,10 +16,13 @@ pub const STATIC_COLLISION_GROUP: Group = Group::GROUP_1;
 pub const DYNAMIC_COLLISION_GROUP: Group = Group::GROUP_3;
 pub const PORTAL_SURFACE_COLLISION_GROUP: Group = Group::GROUP_4;
 
+pub const SAMPLE_LEVEL_PATH: &str = "levels/sample.gltf";
+
 pub fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    asset_server: Res<AssetServer>,
 ) {
     commands.spawn((
         Name::new("Ground"),
@@ -28,6 +38,8 @@ pub fn setup(
 
     commands.spawn((
         Name::new("Cube"),
+        PortalTraveller,
+        Dissolvable,
         Collider::cuboid(0.5, 0.5, 0.5), // m^3
         RigidBody::Dynamic,
         CollisionGroups::new(DYNAMIC_COLLISION_GROUP, Group::all()),
@@ -80,6 +92,25 @@ pub fn setup(
         &mut materials,
     );
 
+    // the portalable wall of the level gets its portal surfaces once loaded
+    level::spawn_level(SAMPLE_LEVEL_PATH, &asset_server, &mut commands);
+
+    commands.spawn((
+        Name::new("Fizzler"),
+        portal_fizzler_bundle(Vec3::new(2., 1.5, 0.05)),
+        PbrBundle {
+            mesh: meshes.add(Cuboid::new(4., 3., 0.1)),
+            material: materials.add(StandardMaterial {
+                base_color: Color::srgba(0.3, 0.6, 1., 0.3),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..Default::default()
+            }),
+            transform: Transform::from_xyz(0., 1.5, 5.),
+            ..Default::default()
+        },
+    ));
+
     // Lights
 
     commands.spawn(DirectionalLightBundle {
@@ -114,31 +145,26 @@ fn spawn_wall(
             },
         ))
         .with_children(|child| {
-            child.spawn((
-                PortalSurface {
-                    size: Vec2::new(4., 2.),
-                },
-                TransformBundle::from_transform(Transform {
-                    translation: Vec3::new(0., 0., 0.1),
-                    ..Default::default()
-                }),
-                Collider::cuboid(2., 1., 0.005),
-                Sensor,
-                CollisionGroups::new(PORTAL_SURFACE_COLLISION_GROUP, PORTAL_RAY_COLLISION_GROUP),
+            child.spawn(portal_surface_bundle(
+                Vec2::new(4., 2.),
+                Transform::from_xyz(0., 0., 0.1),
             ));
 
-            child.spawn((
-                PortalSurface {
-                    size: Vec2::new(4., 2.),
-                },
-                TransformBundle::from_transform(Transform {
-                    translation: Vec3::new(0., 0., -0.1),
-                    rotation: Quat::from_rotation_y(std::f32::consts::PI),
-                    ..Default::default()
-                }),
-                Collider::cuboid(2., 1., 0.005),
-                Sensor,
-                CollisionGroups::new(PORTAL_SURFACE_COLLISION_GROUP, PORTAL_RAY_COLLISION_GROUP),
+            child.spawn(portal_surface_bundle(
+                Vec2::new(4., 2.),
+                Transform::from_xyz(0., 0., -0.1)
+                    .with_rotation(Quat::from_rotation_y(std::f32::consts::PI)),
             ));
         });
 }
+
+/// A thin sensor that the portal gun's ray can hit, facing its local +Z.
+pub fn portal_surface_bundle(size: Vec2, transform: Transform) -> impl Bundle {
+    (
+        PortalSurface { size },
+        TransformBundle::from_transform(transform),
+        Collider::cuboid(size.x / 2., size.y / 2., 0.005),
+        Sensor,
+        CollisionGroups::new(PORTAL_SURFACE_COLLISION_GROUP, PORTAL_RAY_COLLISION_GROUP),
+    )
+}
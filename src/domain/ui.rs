@@ -3,40 +3,43 @@ use bevy::{
     render::render_resource::{AsBindGroup, ShaderRef},
 };
 
+/// Spawns a crosshair in the viewport of each of the `player_cameras`.
 pub fn setup(
-    main_camera: In<Entity>,
+    player_cameras: In<Vec<Entity>>,
     mut commands: Commands,
     mut ui_materials: ResMut<Assets<CrosshairMaterial>>,
 ) {
-    commands
-        .spawn((
-            Name::new("Crosshair"),
-            NodeBundle {
-                style: Style {
-                    width: Val::Percent(100.),
-                    height: Val::Percent(100.),
-                    align_items: AlignItems::Center,
-                    justify_content: JustifyContent::Center,
+    for &camera in player_cameras.iter() {
+        commands
+            .spawn((
+                Name::new("Crosshair"),
+                NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.),
+                        height: Val::Percent(100.),
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::Center,
+                        ..Default::default()
+                    },
                     ..Default::default()
                 },
-                ..Default::default()
-            },
-            TargetCamera(main_camera.0),
-        ))
-        .with_children(|child| {
-            child.spawn((MaterialNodeBundle {
-                material: ui_materials.add(CrosshairMaterial {
-                    color: Color::WHITE.to_linear().to_vec4(),
-                }),
-                style: Style {
-                    position_type: PositionType::Absolute,
-                    width: Val::Px(18.),
-                    height: Val::Px(18.),
+                TargetCamera(camera),
+            ))
+            .with_children(|child| {
+                child.spawn((MaterialNodeBundle {
+                    material: ui_materials.add(CrosshairMaterial {
+                        color: Color::WHITE.to_linear().to_vec4(),
+                    }),
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        width: Val::Px(18.),
+                        height: Val::Px(18.),
+                        ..Default::default()
+                    },
                     ..Default::default()
-                },
-                ..Default::default()
-            },));
-        });
+                },));
+            });
+    }
 }
 
 #[derive(Debug, Clone, AsBindGroup, Asset, TypePath)]
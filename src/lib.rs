@@ -1,4 +1,5 @@
 pub mod domain;
+pub mod physics;
 pub mod resource;
 
 use bevy::{prelude::*, render::view::RenderLayers};
@@ -8,11 +9,18 @@ use once_cell::sync::Lazy;
 pub static ALL_RENDER_LAYERS: Lazy<RenderLayers> = Lazy::new(|| {
     #[cfg(feature = "debug")]
     use bevy_editor_pls::default_windows::cameras::EDITOR_RENDER_LAYER;
-    use domain::{player::PLAYER_RENDER_LAYER, scene::GROUND_RENDER_LAYER};
+    use domain::{
+        player::{player_render_layer, MAX_LOCAL_PLAYERS},
+        portal::PORTAL_RENDER_LAYER,
+        scene::GROUND_RENDER_LAYER,
+    };
 
-    let rl = RenderLayers::none()
-        .with(GROUND_RENDER_LAYER)
-        .with(PLAYER_RENDER_LAYER);
+    let rl = (0..MAX_LOCAL_PLAYERS).fold(
+        RenderLayers::none()
+            .with(GROUND_RENDER_LAYER)
+            .with(PORTAL_RENDER_LAYER),
+        |rl, index| rl.with(player_render_layer(index)),
+    );
 
     cfg_if! {
         if #[cfg(feature = "debug")] {
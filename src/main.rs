@@ -1,8 +1,8 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use bevy::prelude::*;
 #[cfg(feature = "debug")]
 use bevy::window::WindowResolution;
+use bevy::{prelude::*, render::camera::CameraUpdateSystem};
 #[cfg(feature = "debug")]
 use bevy_editor_pls::EditorPlugin;
 // use bevy_gltf_components::ComponentsFromGltfPlugin;
@@ -10,13 +10,14 @@ use bevy_portals::{
     domain::{
         debug_info,
         input::{self, ButtonInputReactions},
-        player,
-        portal::{self, Portal1, Portal2, PortalPlugin},
+        level, player,
+        portal::{self, collision::PortalPhysicsHooks, PortalPlugin},
         scene,
         ui::{self, CrosshairMaterial},
         AppExt,
     },
-    resource::{Controls, ControlsConfig, Fov, MouseSensitivity},
+    physics::{self, PhysicsSchedule},
+    resource::{Fov, LocalPlayers, MouseSensitivity, Pause},
 };
 use bevy_rapier3d::prelude::*;
 // use bevy_registry_export::*;
@@ -27,7 +28,8 @@ fn main() {
     App::new()
         .add_plugins((
             DefaultPlugins,
-            RapierPhysicsPlugin::<NoUserData>::default(),
+            physics::TimePlugin,
+            RapierPhysicsPlugin::<PortalPhysicsHooks>::default().in_schedule(PhysicsSchedule),
             #[cfg(feature = "debug")]
             RapierDebugRenderPlugin::default().disabled(),
             #[cfg(feature = "debug")]
@@ -39,17 +41,24 @@ fn main() {
             }),
             // ExportRegistryPlugin::default(),
             // ComponentsFromGltfPlugin::default(),
-            TnuaControllerPlugin::default(),
-            TnuaRapier3dPlugin::default(),
+            TnuaControllerPlugin::new(PhysicsSchedule),
+            TnuaRapier3dPlugin::new(PhysicsSchedule),
         ))
+        // a single step per run of the `PhysicsSchedule`, by the delta of the `PhysicsTime`,
+        // so that rapier follows the changes of its timestep
+        .insert_resource(TimestepMode::Variable {
+            max_dt: f32::INFINITY,
+            time_scale: 1.,
+            substeps: 1,
+        })
         .register_types() // domain::AppExt
-        .init_resource::<ControlsConfig>()
         .init_resource::<MouseSensitivity>()
         .init_resource::<Fov>()
-        .init_resource::<Controls>()
+        .init_resource::<LocalPlayers>()
+        .init_resource::<Pause>()
         .add_plugins((
             UiMaterialPlugin::<CrosshairMaterial>::default(),
-            PortalPlugin::<Portal1, Portal2>::default(),
+            PortalPlugin::default(),
         ))
         .add_systems(
             Startup,
@@ -63,23 +72,31 @@ fn main() {
         .add_systems(
             Update,
             (
-                input::input_mappings.before(ButtonInputReactions),
-                (
-                    player::movement.in_set(TnuaUserControlsSystemSet),
-                    debug_info::player_is_grounded,
-                )
+                (input::physics_time_controls, input::input_mappings)
                     .chain()
-                    .in_set(ButtonInputReactions),
+                    .before(ButtonInputReactions),
+                debug_info::player_is_grounded,
                 player::rotation,
+                player::split_screen,
                 input::cursor_grab,
                 input::cursor_ungrab,
                 input::exit_on_primary_close,
+                (level::insert_portalable, level::spawn_portalable_surfaces).chain(),
             ),
         )
-        .add_systems(PreUpdate, portal::resize_portal_view_image)
+        .add_systems(
+            PhysicsSchedule,
+            player::movement.in_set(TnuaUserControlsSystemSet),
+        )
         .add_systems(
             PostUpdate,
-            (portal::move_portal_camera, portal::portal_camera_gizmo).chain(),
+            (
+                portal::move_portal_camera
+                    .before(TransformSystem::TransformPropagate)
+                    .before(CameraUpdateSystem),
+                portal::portal_camera_gizmo,
+            )
+                .chain(),
         )
         .run();
 }
@@ -1,36 +1,124 @@
 use std::time::Duration;
 
-use bevy::diagnostic::{Diagnostic, DiagnosticId, DiagnosticPath, Diagnostics, RegisterDiagnostic};
+use bevy::diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic};
 use bevy::ecs::schedule::ScheduleLabel;
+use bevy::input::InputSystem;
 use bevy::prelude::*;
+use bevy_rapier3d::{dynamics::RigidBody, plugin::PhysicsSet};
 
-pub const PHYSICS_FPS: DiagnosticId =
-    DiagnosticId::from_u128(168810318229280110473455791631253127370);
+use crate::resource::PhysicsControlsConfig;
 
+pub mod snapshot;
+
+pub const PHYSICS_FPS: DiagnosticPath = DiagnosticPath::const_new("physics_fps");
+/// The ticks dropped by the [`PhysicsOverrunPolicy`] per frame
+pub const PHYSICS_DROPPED_TICKS: DiagnosticPath =
+    DiagnosticPath::const_new("physics_dropped_ticks");
+/// The average execution time of a tick, ms
+pub const PHYSICS_TICK_TIME: DiagnosticPath = DiagnosticPath::const_new("physics_tick_time");
+/// The ticks owed to the real time after the frame
+pub const PHYSICS_DEBT: DiagnosticPath = DiagnosticPath::const_new("physics_debt");
+
+/// 64 Hz
 pub const DEFAULT_TIMESTEP: Duration = Duration::from_micros(15625);
-pub const MAX_PHYSICS_EXEC_TIME: Duration = Duration::from_micros(15625);
+pub const DEFAULT_MAX_PHYSICS_EXEC_TIME: Duration = Duration::from_micros(15625);
+pub const DEFAULT_MAX_PHYSICS_DEBT: u32 = 3;
 
 #[derive(Resource, Default)]
 pub struct DiagnosticFrameCount(u32);
 
+/// What the ticks have cost since the last diagnostics report.
+#[derive(Resource, Default)]
+pub struct PhysicsBudgetStats {
+    exec_time: Duration,
+    dropped_ticks: f64,
+}
+
+/// How much time per frame the [`PhysicsSchedule`] may take,
+/// and what happens to the ticks it could not run in time.
+#[derive(Debug, Clone, Copy, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct PhysicsBudget {
+    /// The ticks stop once they have taken this long in a frame.
+    pub max_exec_time: Duration,
+    pub overrun: PhysicsOverrunPolicy,
+}
+
+impl Default for PhysicsBudget {
+    fn default() -> Self {
+        Self {
+            max_exec_time: DEFAULT_MAX_PHYSICS_EXEC_TIME,
+            overrun: PhysicsOverrunPolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum PhysicsOverrunPolicy {
+    /// Owes at most `max_debt` ticks to the next frames, and drops the rest.
+    Drop { max_debt: u32 },
+    /// Drops all the whole ticks owed, so that the simulation slows down evenly
+    /// instead of speeding up to catch up later.
+    SlowDown,
+    /// Owes all the ticks to the next frames, which run them as the budget allows.
+    CatchUp,
+}
+
+impl Default for PhysicsOverrunPolicy {
+    fn default() -> Self {
+        Self::Drop {
+            max_debt: DEFAULT_MAX_PHYSICS_DEBT,
+        }
+    }
+}
+
 pub struct TimePlugin;
 
 impl Plugin for TimePlugin {
     fn build(&self, app: &mut App) {
         app.init_schedule(PhysicsSchedule)
             .register_type::<PhysicsTime>()
+            .register_type::<PhysicsInterpolation>()
+            .register_type::<PhysicsBudget>()
             .init_resource::<PhysicsTime>()
+            .init_resource::<PhysicsBudget>()
+            .init_resource::<PhysicsBudgetStats>()
+            .init_resource::<snapshot::PhysicsCheckpoint>()
+            .init_resource::<PhysicsControlsConfig>()
             .init_resource::<DiagnosticFrameCount>()
+            .register_diagnostic(Diagnostic::new(PHYSICS_FPS).with_max_history_length(10))
+            .register_diagnostic(Diagnostic::new(PHYSICS_DROPPED_TICKS).with_max_history_length(10))
             .register_diagnostic(
-                Diagnostic::new(DiagnosticPath::const_new("physics_fps"))
+                Diagnostic::new(PHYSICS_TICK_TIME)
+                    .with_suffix("ms")
                     .with_max_history_length(10),
             )
-            .add_systems(PhysicsSchedule, diagnostics_count)
-            .add_systems(Update, diagnostics_report)
-            .add_systems(PreUpdate, run_physics_schedule);
+            .register_diagnostic(Diagnostic::new(PHYSICS_DEBT).with_max_history_length(10))
+            .add_systems(
+                PhysicsSchedule,
+                (
+                    diagnostics_count,
+                    record_physics_transforms.after(PhysicsSet::Writeback),
+                ),
+            )
+            .add_systems(Update, (diagnostics_report, interpolate_dynamic_bodies))
+            .add_systems(
+                PreUpdate,
+                (
+                    restore_physics_transforms,
+                    snapshot::physics_checkpoint_controls,
+                    run_physics_schedule,
+                    interpolate_transforms,
+                )
+                    .chain()
+                    .after(InputSystem),
+            );
     }
 }
 
+/// Runs every `timestep` of the [`PhysicsTime`], see [`run_physics_schedule`].
+///
+/// The generic [`Time`] is the [`PhysicsTime`] while it runs.
 #[derive(ScheduleLabel, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct PhysicsSchedule;
 
@@ -41,6 +129,10 @@ pub trait PhysicsTimeExt {
     fn resume(&mut self);
     fn step(&mut self);
     fn run(&mut self, speed: f32);
+    /// The speed of the running mode, which is kept while paused.
+    fn speed(&self) -> f32;
+    /// Changes the speed of the running mode, without resuming if paused.
+    fn set_speed(&mut self, speed: f32);
 }
 
 impl PhysicsTimeExt for PhysicsTime {
@@ -61,6 +153,21 @@ impl PhysicsTimeExt for PhysicsTime {
         self.context_mut()
             .set_mode(PhysicsTimeMode::Running { speed });
     }
+
+    fn speed(&self) -> f32 {
+        match self.context().old_mode {
+            PhysicsTimeMode::Running { speed } => speed,
+            _ => 1.,
+        }
+    }
+
+    fn set_speed(&mut self, speed: f32) {
+        let context = self.context_mut();
+        context.old_mode = PhysicsTimeMode::Running { speed };
+        if let PhysicsTimeMode::Running { .. } = context.mode {
+            context.mode = context.old_mode;
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, Reflect)]
@@ -70,6 +177,8 @@ pub struct PhysicsTimeInner {
     old_mode: PhysicsTimeMode,
     pub timestep: Duration,
     pub overstep: Duration,
+    /// The number of the ticks run so far.
+    tick: u64,
 }
 
 impl PhysicsTimeInner {
@@ -79,6 +188,20 @@ impl PhysicsTimeInner {
         }
         self.mode = mode;
     }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// How far the rendered frame is from the previous tick to the last one, from `0` to `1`.
+    ///
+    /// While paused, the last tick is shown as it is.
+    pub fn overstep_fraction(&self) -> f32 {
+        match self.mode {
+            PhysicsTimeMode::Paused => 1.,
+            _ => (self.overstep.as_secs_f32() / self.timestep.as_secs_f32()).min(1.),
+        }
+    }
 }
 
 impl Default for PhysicsTimeInner {
@@ -88,6 +211,7 @@ impl Default for PhysicsTimeInner {
             old_mode: PhysicsTimeMode::default(),
             timestep: DEFAULT_TIMESTEP,
             overstep: Duration::ZERO,
+            tick: 0,
         }
     }
 }
@@ -111,7 +235,7 @@ fn accumulate_time(time: &mut PhysicsTime, delta: Duration) {
         PhysicsTimeMode::Paused => (),
         PhysicsTimeMode::OneTick => (),
         PhysicsTimeMode::Running { speed } => {
-            if speed == std::f32::INFINITY {
+            if speed == f32::INFINITY {
                 context.overstep = Duration::MAX;
             } else {
                 context.overstep = context.overstep.saturating_add(delta.mul_f32(speed));
@@ -140,31 +264,156 @@ fn expend_time(time: &mut PhysicsTime) -> bool {
     };
 
     if result {
+        context.tick += 1;
         let timestep = context.timestep;
         time.advance_by(timestep);
     }
     result
 }
 
-fn limit_overstep(time: &mut PhysicsTime) {
+/// Returns the number of the ticks dropped.
+fn limit_overstep(time: &mut PhysicsTime, policy: PhysicsOverrunPolicy) -> f64 {
     let context = time.context_mut();
-    context.overstep = context.overstep.min(context.timestep * 3);
+    let limit = match policy {
+        PhysicsOverrunPolicy::Drop { max_debt } => context.timestep * max_debt,
+        PhysicsOverrunPolicy::SlowDown => context.timestep,
+        PhysicsOverrunPolicy::CatchUp => return 0.,
+    };
+    if context.overstep <= limit {
+        return 0.;
+    }
+    let dropped = match policy {
+        PhysicsOverrunPolicy::SlowDown => {
+            // the fraction of a tick is kept, so that the interpolation does not jump
+            let timestep = context.timestep.as_nanos();
+            let kept = context.overstep.as_nanos() % timestep;
+            context.overstep - Duration::from_nanos(kept as u64)
+        }
+        _ => context.overstep - limit,
+    };
+    context.overstep -= dropped;
+    dropped.as_secs_f64() / context.timestep.as_secs_f64()
 }
 
 pub fn run_physics_schedule(world: &mut World) {
     let delta = world.resource::<Time<Virtual>>().delta();
     accumulate_time(&mut world.resource_mut::<PhysicsTime>(), delta);
 
+    let budget = *world.resource::<PhysicsBudget>();
     let time = std::time::Instant::now();
     world.schedule_scope(PhysicsSchedule, |world, schedule| {
         while expend_time(&mut world.resource_mut::<PhysicsTime>()) {
+            *world.resource_mut::<Time>() = world.resource::<PhysicsTime>().as_generic();
             schedule.run(world);
-            if time.elapsed() >= MAX_PHYSICS_EXEC_TIME {
+            if time.elapsed() >= budget.max_exec_time {
                 break;
             }
         }
-        limit_overstep(&mut world.resource_mut::<PhysicsTime>());
     });
+    *world.resource_mut::<Time>() = world.resource::<Time<Virtual>>().as_generic();
+
+    let dropped_ticks = limit_overstep(&mut world.resource_mut::<PhysicsTime>(), budget.overrun);
+    let mut stats = world.resource_mut::<PhysicsBudgetStats>();
+    stats.exec_time += time.elapsed();
+    stats.dropped_ticks += dropped_ticks;
+}
+
+/// Smooths the [`Transform`] of a body moved in the [`PhysicsSchedule`]
+/// in between the ticks, by rendering it between the poses of the last two ticks.
+///
+/// Inserted automatically into every [`RigidBody::Dynamic`].
+/// The changes to the `Transform` made outside of the `PhysicsSchedule` are overwritten,
+/// except for the rotation, if it is not interpolated.
+#[derive(Debug, Clone, Copy, Component, Reflect)]
+#[reflect(Component)]
+pub struct PhysicsInterpolation {
+    /// Whether the rotation is interpolated too.
+    ///
+    /// Not for the bodies rotated every frame, like the players turned by the mouse.
+    pub rotation: bool,
+    previous: Option<Transform>,
+    current: Option<Transform>,
+    teleported: bool,
+}
+
+impl Default for PhysicsInterpolation {
+    fn default() -> Self {
+        Self {
+            rotation: true,
+            previous: None,
+            current: None,
+            teleported: false,
+        }
+    }
+}
+
+impl PhysicsInterpolation {
+    pub fn translation_only() -> Self {
+        Self {
+            rotation: false,
+            ..Default::default()
+        }
+    }
+
+    /// Shows the body right where the current tick leaves it, instead of moving it there
+    /// from where it was on the previous tick.
+    pub fn teleport(&mut self) {
+        self.teleported = true;
+    }
+}
+
+pub fn interpolate_dynamic_bodies(
+    body_q: Query<(Entity, &RigidBody), (Added<RigidBody>, Without<PhysicsInterpolation>)>,
+    mut commands: Commands,
+) {
+    for (entity, body) in body_q.iter() {
+        if let RigidBody::Dynamic = body {
+            commands
+                .entity(entity)
+                .insert(PhysicsInterpolation::default());
+        }
+    }
+}
+
+/// Puts the bodies back where the last tick has left them, before the next ticks.
+fn restore_physics_transforms(mut body_q: Query<(&mut Transform, &PhysicsInterpolation)>) {
+    for (mut transform, interpolation) in body_q.iter_mut() {
+        let Some(current) = interpolation.current else {
+            continue;
+        };
+        transform.translation = current.translation;
+        if interpolation.rotation {
+            transform.rotation = current.rotation;
+        }
+    }
+}
+
+pub fn record_physics_transforms(mut body_q: Query<(&Transform, &mut PhysicsInterpolation)>) {
+    for (&transform, mut interpolation) in body_q.iter_mut() {
+        interpolation.previous = match interpolation.current {
+            Some(current) if !interpolation.teleported => Some(current),
+            _ => Some(transform),
+        };
+        interpolation.current = Some(transform);
+        interpolation.teleported = false;
+    }
+}
+
+fn interpolate_transforms(
+    physics_time: Res<PhysicsTime>,
+    mut body_q: Query<(&mut Transform, &PhysicsInterpolation)>,
+) {
+    let t = physics_time.context().overstep_fraction();
+    for (mut transform, interpolation) in body_q.iter_mut() {
+        let (Some(previous), Some(current)) = (interpolation.previous, interpolation.current)
+        else {
+            continue;
+        };
+        transform.translation = previous.translation.lerp(current.translation, t);
+        if interpolation.rotation {
+            transform.rotation = previous.rotation.slerp(current.rotation, t);
+        }
+    }
 }
 
 fn diagnostics_count(mut frame_count: ResMut<DiagnosticFrameCount>) {
@@ -174,12 +423,84 @@ fn diagnostics_count(mut frame_count: ResMut<DiagnosticFrameCount>) {
 fn diagnostics_report(
     mut diagnostics: Diagnostics,
     mut frame_count: ResMut<DiagnosticFrameCount>,
+    mut stats: ResMut<PhysicsBudgetStats>,
+    physics_time: Res<PhysicsTime>,
     time: Res<Time<Real>>,
 ) {
     let delta = time.delta_seconds_f64();
     if delta == 0. {
         return;
     }
-    diagnostics.add_measurement(PHYSICS_FPS, || frame_count.0 as f64 / delta);
+    diagnostics.add_measurement(&PHYSICS_FPS, || frame_count.0 as f64 / delta);
+    if frame_count.0 > 0 {
+        diagnostics.add_measurement(&PHYSICS_TICK_TIME, || {
+            stats.exec_time.as_secs_f64() * 1000. / frame_count.0 as f64
+        });
+    }
+    diagnostics.add_measurement(&PHYSICS_DROPPED_TICKS, || stats.dropped_ticks);
+    diagnostics.add_measurement(&PHYSICS_DEBT, || {
+        let context = physics_time.context();
+        context.overstep.as_secs_f64() / context.timestep.as_secs_f64()
+    });
     frame_count.0 = 0;
+    *stats = PhysicsBudgetStats::default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn physics_time_with_overstep(overstep: Duration) -> PhysicsTime {
+        let mut time = PhysicsTime::default();
+        time.context_mut().overstep = overstep;
+        time
+    }
+
+    #[test]
+    fn drop_keeps_the_max_debt() {
+        let policy = PhysicsOverrunPolicy::Drop { max_debt: 3 };
+        let mut time = physics_time_with_overstep(DEFAULT_TIMESTEP * 11 / 2);
+
+        assert_eq!(limit_overstep(&mut time, policy), 2.5);
+        assert_eq!(time.context().overstep, DEFAULT_TIMESTEP * 3);
+    }
+
+    #[test]
+    fn drop_within_the_max_debt() {
+        let policy = PhysicsOverrunPolicy::Drop { max_debt: 3 };
+        let mut time = physics_time_with_overstep(DEFAULT_TIMESTEP * 5 / 2);
+
+        assert_eq!(limit_overstep(&mut time, policy), 0.);
+        assert_eq!(time.context().overstep, DEFAULT_TIMESTEP * 5 / 2);
+    }
+
+    #[test]
+    fn slow_down_keeps_the_sub_tick_remainder() {
+        let mut time = physics_time_with_overstep(DEFAULT_TIMESTEP * 9 / 4);
+
+        assert_eq!(
+            limit_overstep(&mut time, PhysicsOverrunPolicy::SlowDown),
+            2.
+        );
+        assert_eq!(time.context().overstep, DEFAULT_TIMESTEP / 4);
+    }
+
+    #[test]
+    fn slow_down_within_a_tick() {
+        let mut time = physics_time_with_overstep(DEFAULT_TIMESTEP);
+
+        assert_eq!(
+            limit_overstep(&mut time, PhysicsOverrunPolicy::SlowDown),
+            0.
+        );
+        assert_eq!(time.context().overstep, DEFAULT_TIMESTEP);
+    }
+
+    #[test]
+    fn catch_up_keeps_all_the_ticks() {
+        let mut time = physics_time_with_overstep(DEFAULT_TIMESTEP * 10);
+
+        assert_eq!(limit_overstep(&mut time, PhysicsOverrunPolicy::CatchUp), 0.);
+        assert_eq!(time.context().overstep, DEFAULT_TIMESTEP * 10);
+    }
 }
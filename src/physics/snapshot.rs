@@ -0,0 +1,282 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_rapier3d::{
+    dynamics::{RapierRigidBodyHandle, Velocity},
+    geometry::RapierColliderHandle,
+    plugin::RapierContext,
+    rapier::prelude::{
+        CCDSolver, ColliderSet, DefaultBroadPhase, ImpulseJointSet, IslandManager,
+        MultibodyJointSet, NarrowPhase, QueryPipeline, RigidBodySet,
+    },
+};
+
+use crate::{
+    domain::portal::{
+        traversal::PortalCrossing, ClosePortal, Portal, PortalChannel, PortalShape, SpawnPortal,
+    },
+    resource::PhysicsControlsConfig,
+};
+
+use super::{PhysicsInterpolation, PhysicsTime};
+
+/// The last [`PhysicsSnapshot`] saved by the player, see [`PhysicsControlsConfig`].
+#[derive(Default, Resource)]
+pub struct PhysicsCheckpoint(pub Option<PhysicsSnapshot>);
+
+/// The state of the simulation at a tick, which can be restored any number of times.
+///
+/// Includes the whole rapier state, so that the ticks after the restore replay the same way,
+/// and the portals, which are respawned with their pairs,
+/// while the ones open at the restore are closed.
+///
+/// The bodies despawned after the snapshot was taken are not brought back,
+/// and the bodies spawned after it are kept as they are.
+/// The state of the character controllers is not included.
+pub struct PhysicsSnapshot {
+    tick: u64,
+    elapsed: Duration,
+    rapier: RapierState,
+    /// The poses of the bodies, which rapier is not aware of
+    bodies: Vec<(Entity, Transform, Option<Velocity>)>,
+    portals: Vec<SpawnPortal>,
+}
+
+#[derive(Clone)]
+struct RapierState {
+    islands: IslandManager,
+    broad_phase: DefaultBroadPhase,
+    narrow_phase: NarrowPhase,
+    bodies: RigidBodySet,
+    colliders: ColliderSet,
+    impulse_joints: ImpulseJointSet,
+    multibody_joints: MultibodyJointSet,
+    ccd_solver: CCDSolver,
+    query_pipeline: QueryPipeline,
+}
+
+impl PhysicsSnapshot {
+    /// Should be taken in between the ticks, when the bodies are not interpolated.
+    pub fn capture(world: &mut World) -> Self {
+        let physics_time = world.resource::<PhysicsTime>();
+        let (tick, elapsed) = (physics_time.context().tick(), physics_time.elapsed());
+
+        let rapier_ctx = world.resource::<RapierContext>();
+        let rapier = RapierState {
+            islands: rapier_ctx.islands.clone(),
+            broad_phase: rapier_ctx.broad_phase.clone(),
+            narrow_phase: rapier_ctx.narrow_phase.clone(),
+            bodies: rapier_ctx.bodies.clone(),
+            colliders: rapier_ctx.colliders.clone(),
+            impulse_joints: rapier_ctx.impulse_joints.clone(),
+            multibody_joints: rapier_ctx.multibody_joints.clone(),
+            ccd_solver: rapier_ctx.ccd_solver.clone(),
+            query_pipeline: rapier_ctx.query_pipeline.clone(),
+        };
+
+        let bodies = world
+            .query::<(
+                Entity,
+                &Transform,
+                Option<&Velocity>,
+                &RapierRigidBodyHandle,
+            )>()
+            .iter(world)
+            .map(|(entity, &transform, velocity, _)| (entity, transform, velocity.copied()))
+            .collect();
+
+        let portals = world
+            .query::<(
+                &Portal,
+                &PortalChannel,
+                &PortalShape,
+                &GlobalTransform,
+                Option<&Parent>,
+            )>()
+            .iter(world)
+            .filter(|(portal, ..)| !portal.is_closing())
+            .map(
+                |(portal, &channel, &shape, portal_gt, surface)| SpawnPortal {
+                    channel,
+                    transform: portal_gt.compute_transform(),
+                    shape,
+                    state: portal.state(),
+                    surface: surface.map(Parent::get),
+                    wall: portal.wall(),
+                },
+            )
+            .collect();
+
+        Self {
+            tick,
+            elapsed,
+            rapier,
+            bodies,
+            portals,
+        }
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Should be restored in between the ticks too, and before the bodies are interpolated.
+    pub fn restore(&self, world: &mut World) {
+        let mut physics_time = world.resource_mut::<PhysicsTime>();
+        let mut context = *physics_time.context();
+        context.tick = self.tick;
+        context.overstep = Duration::ZERO;
+        // the time cannot be moved backwards, so it is started over
+        *physics_time = PhysicsTime::new_with(context);
+        physics_time.advance_to(self.elapsed);
+
+        world.resource_scope(|world, mut rapier_ctx: Mut<RapierContext>| {
+            let RapierState {
+                islands,
+                broad_phase,
+                narrow_phase,
+                bodies,
+                colliders,
+                impulse_joints,
+                multibody_joints,
+                ccd_solver,
+                query_pipeline,
+            } = self.rapier.clone();
+            let rapier_ctx = &mut *rapier_ctx;
+            rapier_ctx.islands = islands;
+            rapier_ctx.broad_phase = broad_phase;
+            rapier_ctx.narrow_phase = narrow_phase;
+            rapier_ctx.bodies = bodies;
+            rapier_ctx.colliders = colliders;
+            rapier_ctx.impulse_joints = impulse_joints;
+            rapier_ctx.multibody_joints = multibody_joints;
+            rapier_ctx.ccd_solver = ccd_solver;
+            rapier_ctx.query_pipeline = query_pipeline;
+
+            // the entities are stored in the user data by bevy_rapier
+            let is_despawned = |user_data: u128| {
+                world
+                    .get_entity(Entity::from_bits(user_data as u64))
+                    .is_none()
+            };
+
+            let despawned_bodies = rapier_ctx
+                .bodies
+                .iter()
+                .filter(|(_, body)| is_despawned(body.user_data))
+                .map(|(handle, _)| handle)
+                .collect::<Vec<_>>();
+            for handle in despawned_bodies {
+                rapier_ctx.bodies.remove(
+                    handle,
+                    &mut rapier_ctx.islands,
+                    &mut rapier_ctx.colliders,
+                    &mut rapier_ctx.impulse_joints,
+                    &mut rapier_ctx.multibody_joints,
+                    true,
+                );
+            }
+
+            let despawned_colliders = rapier_ctx
+                .colliders
+                .iter()
+                .filter(|(_, collider)| is_despawned(collider.user_data))
+                .map(|(handle, _)| handle)
+                .collect::<Vec<_>>();
+            for handle in despawned_colliders {
+                rapier_ctx.colliders.remove(
+                    handle,
+                    &mut rapier_ctx.islands,
+                    &mut rapier_ctx.bodies,
+                    true,
+                );
+            }
+
+            // bevy_rapier recreates the bodies and the colliders spawned after the snapshot,
+            // once their handles are gone
+            let new_bodies = world
+                .query::<(Entity, &RapierRigidBodyHandle)>()
+                .iter(world)
+                .filter(|(_, handle)| !rapier_ctx.bodies.contains(handle.0))
+                .map(|(entity, _)| entity)
+                .collect::<Vec<_>>();
+            for entity in new_bodies {
+                world.entity_mut(entity).remove::<RapierRigidBodyHandle>();
+            }
+
+            let new_colliders = world
+                .query::<(Entity, &RapierColliderHandle)>()
+                .iter(world)
+                .filter(|(_, handle)| !rapier_ctx.colliders.contains(handle.0))
+                .map(|(entity, _)| entity)
+                .collect::<Vec<_>>();
+            for entity in new_colliders {
+                world.entity_mut(entity).remove::<RapierColliderHandle>();
+            }
+        });
+
+        for &(entity, transform, velocity) in &self.bodies {
+            let Some(mut body) = world.get_entity_mut(entity) else {
+                continue;
+            };
+            body.insert(transform);
+            if let Some(velocity) = velocity {
+                body.insert(velocity);
+            }
+            if let Some(mut interpolation) = body.get_mut::<PhysicsInterpolation>() {
+                interpolation.previous = Some(transform);
+                interpolation.current = Some(transform);
+            }
+            // the body has not come from the other side of a portal
+            if body.contains::<PortalCrossing>() {
+                body.insert(PortalCrossing::default());
+            }
+        }
+
+        let channels = world
+            .query::<(&Portal, &PortalChannel)>()
+            .iter(world)
+            .filter(|(portal, _)| !portal.is_closing())
+            .map(|(_, &channel)| channel)
+            .collect::<Vec<_>>();
+        for channel in channels {
+            world.trigger(ClosePortal { channel });
+        }
+        world.flush_commands();
+        for &spawn_portal in &self.portals {
+            if spawn_portal
+                .surface
+                .is_some_and(|surface| world.get_entity(surface).is_none())
+            {
+                continue;
+            }
+            world.trigger(spawn_portal);
+            // so that the next portal is paired with this one
+            world.flush_commands();
+        }
+    }
+}
+
+/// Saves and loads the [`PhysicsCheckpoint`] in between the ticks.
+pub fn physics_checkpoint_controls(world: &mut World) {
+    let controls_config = world.resource::<PhysicsControlsConfig>();
+    let input_key_code = world.resource::<ButtonInput<KeyCode>>();
+    let input_mouse_button = world.resource::<ButtonInput<MouseButton>>();
+    let save = controls_config
+        .save_checkpoint
+        .just_pressed(input_key_code, input_mouse_button);
+    let load = controls_config
+        .load_checkpoint
+        .just_pressed(input_key_code, input_mouse_button);
+
+    if save {
+        let snapshot = PhysicsSnapshot::capture(world);
+        world.resource_mut::<PhysicsCheckpoint>().0 = Some(snapshot);
+    } else if load {
+        world.resource_scope(|world, checkpoint: Mut<PhysicsCheckpoint>| {
+            if let Some(snapshot) = &checkpoint.0 {
+                snapshot.restore(world);
+            }
+        });
+    }
+}
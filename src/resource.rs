@@ -1,6 +1,7 @@
 use bevy::prelude::*;
 
-#[derive(Resource)]
+/// Whether the simulation is paused, see [`PhysicsControlsConfig`].
+#[derive(Debug, Default, Resource)]
 pub struct Pause(pub bool);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -33,7 +34,19 @@ impl Control {
     }
 }
 
-#[derive(Debug, Resource)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LookControls {
+    Mouse,
+    Keys {
+        up: Control,
+        down: Control,
+        left: Control,
+        right: Control,
+    },
+}
+
+/// The bindings of the player it is attached to.
+#[derive(Debug, Clone, Component)]
 pub struct ControlsConfig {
     pub up: Control,
     pub down: Control,
@@ -44,6 +57,37 @@ pub struct ControlsConfig {
     pub shoot1: Control,
     pub shoot2: Control,
     pub remove_portals: Control,
+    pub close_portal1: Control,
+    pub close_portal2: Control,
+    pub look: LookControls,
+}
+
+impl ControlsConfig {
+    /// The bindings of the local co-op players, sharing the keyboard with the first player.
+    pub fn for_player(index: usize) -> Self {
+        match index {
+            0 => Self::default(),
+            _ => Self {
+                up: Control::KeyCode(KeyCode::KeyI),
+                down: Control::KeyCode(KeyCode::KeyK),
+                left: Control::KeyCode(KeyCode::KeyJ),
+                right: Control::KeyCode(KeyCode::KeyL),
+                run: Control::KeyCode(KeyCode::ShiftRight),
+                jump: Control::KeyCode(KeyCode::Enter),
+                shoot1: Control::KeyCode(KeyCode::KeyU),
+                shoot2: Control::KeyCode(KeyCode::KeyO),
+                remove_portals: Control::KeyCode(KeyCode::KeyP),
+                close_portal1: Control::KeyCode(KeyCode::BracketLeft),
+                close_portal2: Control::KeyCode(KeyCode::BracketRight),
+                look: LookControls::Keys {
+                    up: Control::KeyCode(KeyCode::ArrowUp),
+                    down: Control::KeyCode(KeyCode::ArrowDown),
+                    left: Control::KeyCode(KeyCode::ArrowLeft),
+                    right: Control::KeyCode(KeyCode::ArrowRight),
+                },
+            },
+        }
+    }
 }
 
 impl Default for ControlsConfig {
@@ -58,10 +102,47 @@ impl Default for ControlsConfig {
             shoot1: Control::MouseButton(MouseButton::Left),
             shoot2: Control::MouseButton(MouseButton::Right),
             remove_portals: Control::KeyCode(KeyCode::KeyR),
+            close_portal1: Control::KeyCode(KeyCode::KeyQ),
+            close_portal2: Control::KeyCode(KeyCode::KeyE),
+            look: LookControls::Mouse,
         }
     }
 }
 
+/// The bindings controlling the [`PhysicsTime`](crate::physics::PhysicsTime),
+/// shared by all the players.
+#[derive(Debug, Clone, Resource)]
+pub struct PhysicsControlsConfig {
+    pub pause: Control,
+    /// Advances the paused simulation by a single tick.
+    pub step: Control,
+    pub slow_motion: Control,
+    pub save_checkpoint: Control,
+    pub load_checkpoint: Control,
+}
+
+impl Default for PhysicsControlsConfig {
+    fn default() -> Self {
+        Self {
+            pause: Control::KeyCode(KeyCode::F5),
+            step: Control::KeyCode(KeyCode::F6),
+            slow_motion: Control::KeyCode(KeyCode::F7),
+            save_checkpoint: Control::KeyCode(KeyCode::F8),
+            load_checkpoint: Control::KeyCode(KeyCode::F9),
+        }
+    }
+}
+
+/// The number of players sharing the screen.
+#[derive(Debug, Clone, Copy, Resource)]
+pub struct LocalPlayers(pub usize);
+
+impl Default for LocalPlayers {
+    fn default() -> Self {
+        Self(1)
+    }
+}
+
 #[derive(Debug, Resource)]
 pub struct MouseSensitivity(pub f32);
 
@@ -105,7 +186,8 @@ impl Default for FieldOfView {
 
 pub type Fov = FieldOfView;
 
-#[derive(Debug, Clone, Copy, Default, Resource)]
+/// The state of the [`ControlsConfig`] of the player it is attached to.
+#[derive(Debug, Clone, Copy, Default, Component)]
 pub struct Controls {
     pub up: bool,
     pub down: bool,
@@ -116,6 +198,10 @@ pub struct Controls {
     pub shoot1: bool,
     pub shoot2: bool,
     pub remove_portals: bool,
+    pub close_portal1: bool,
+    pub close_portal2: bool,
+    /// The mouse motion, or its equivalent from the look keys.
+    pub look: Vec2,
 }
 
 impl Controls {